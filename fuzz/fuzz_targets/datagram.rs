@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udp_mud::datagram::{decode, encode};
+
+fuzz_target!(|data: &[u8]| {
+    let (header, packet) = match decode(data) {
+        Ok(decoded) => decoded,
+        Err(_) => return,
+    };
+
+    // compressors needn't agree byte for byte, so only the packet is compared
+    let buf = encode(
+        &packet,
+        header.sender,
+        header.sequence,
+        header.flags.compressed,
+    )
+    .unwrap();
+    assert_eq!(decode(&buf).unwrap().1, packet);
+});
@@ -0,0 +1,40 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::cell::RefCell;
+use std::net::{SocketAddr, UdpSocket};
+use udp_mud::{App, Args, Packet};
+use udp_mud_fuzz::check_decode;
+
+struct Harness {
+    app: App,
+    /// Stands in for the remote peer so that replies have somewhere to go.
+    peer: UdpSocket,
+}
+
+thread_local! {
+    static HARNESS: RefCell<Harness> = RefCell::new(Harness {
+        app: App::new(Args {
+            username: "fuzz".to_string(),
+            bind_addrs: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+            connect: None,
+            tcp: false,
+            capture: None,
+            replay: None,
+        }),
+        peer: UdpSocket::bind("127.0.0.1:0").unwrap(),
+    });
+}
+
+fuzz_target!(|data: &[u8]| {
+    let packet = match check_decode::<Packet>(data) {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    HARNESS.with(|harness| {
+        let mut harness = harness.borrow_mut();
+        let from = harness.peer.local_addr().unwrap();
+        harness.app.on_packet(from, packet);
+    });
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use udp_mud::Packet;
+use udp_mud_fuzz::check_decode;
+
+fuzz_target!(|data: &[u8]| {
+    check_decode::<Packet>(data);
+});
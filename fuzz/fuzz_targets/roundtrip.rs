@@ -0,0 +1,48 @@
+#![no_main]
+
+//! Differential target: every value must decode back to itself, both owned
+//! and borrowed.
+
+use libfuzzer_sys::fuzz_target;
+use protocol::testing::encode;
+use protocol::{Decode, DecodeRef, Var, MAX_DATAGRAM_LEN};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Input = (
+    (bool, u8, i16, u32, i64, u128),
+    (char, String, Vec<u8>),
+    Option<Vec<(bool, String)>>,
+    BTreeMap<String, Vec<bool>>,
+    BTreeSet<i32>,
+    [u16; 3],
+);
+
+type BorrowedInput<'a> = (
+    (bool, u8, i16, u32, i64, u128),
+    (char, &'a str, &'a [u8]),
+    Option<Vec<(bool, &'a str)>>,
+    BTreeMap<&'a str, Vec<bool>>,
+    BTreeSet<i32>,
+    [u16; 3],
+);
+
+fuzz_target!(|input: (Input, u64, i64)| {
+    let (value, unsigned, signed) = input;
+
+    let buf = encode(&value);
+    if buf.len() > MAX_DATAGRAM_LEN {
+        // too big to have come from the network, so the decode limits apply
+        return;
+    }
+
+    let decoded = Input::decode(&mut buf.as_slice()).unwrap();
+    assert_eq!(decoded, value);
+
+    let borrowed = BorrowedInput::decode_ref(&mut buf.as_slice()).unwrap();
+    assert_eq!(encode(&borrowed), buf);
+
+    let buf = encode(&(Var(unsigned), Var(signed)));
+    let (Var(decoded_unsigned), Var(decoded_signed)) =
+        <(Var<u64>, Var<i64>)>::decode(&mut buf.as_slice()).unwrap();
+    assert_eq!((decoded_unsigned, decoded_signed), (unsigned, signed));
+});
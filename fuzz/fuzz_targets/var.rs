@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::Var;
+use udp_mud_fuzz::check_decode;
+
+fuzz_target!(|data: &[u8]| {
+    check_decode::<Var<u16>>(data);
+    check_decode::<Var<u32>>(data);
+    check_decode::<Var<u64>>(data);
+    check_decode::<Var<u128>>(data);
+    check_decode::<Var<i32>>(data);
+    check_decode::<Var<i64>>(data);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::Var;
+use udp_mud_fuzz::check_decode;
+
+fuzz_target!(|data: &[u8]| {
+    check_decode::<Vec<u8>>(data);
+    check_decode::<Vec<bool>>(data);
+    check_decode::<Vec<String>>(data);
+    check_decode::<Vec<Var<u32>>>(data);
+    check_decode::<Vec<Option<Vec<u16>>>>(data);
+});
@@ -0,0 +1,24 @@
+//! Shared checks for the targets in `fuzz_targets/`.
+//!
+//! Run a target with `cargo fuzz run <target>` from the repository root. The
+//! seed inputs in `corpus/` are encoded from a real handshake between two
+//! peers and are kept up to date by the `fuzz_corpus` test in `udp-mud`.
+
+use protocol::testing::encode;
+use protocol::{Decode, Encode};
+
+/// Decodes a `T` from untrusted input and checks that whatever was decoded
+/// encodes consistently: `encoded_len` matches the bytes written and decoding
+/// them again yields the same encoding.
+///
+/// The input itself is not expected to round-trip byte for byte, since
+/// extensible structs drop fields they don't know about.
+pub fn check_decode<T: Decode + Encode>(mut data: &[u8]) -> Option<T> {
+    let value = T::decode(&mut data).ok()?;
+    let buf = encode(&value);
+
+    let decoded = T::decode(&mut buf.as_slice()).expect("re-encoded value failed to decode");
+    assert_eq!(encode(&decoded), buf, "re-encoded value changed");
+
+    Some(value)
+}
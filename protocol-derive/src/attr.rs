@@ -0,0 +1,285 @@
+use proc_macro2::Span;
+use syn::{
+    spanned::Spanned, Attribute, Data, DeriveInput, Error, Field, Fields, Lit, Meta, NestedMeta,
+    Path, Result, Type,
+};
+
+/// Options set on a struct or enum with `#[protocol(...)]`.
+#[derive(Default)]
+pub struct ContainerAttrs {
+    /// Prefix the struct's fields with their length, so that fields added
+    /// `since` a later version can be left off or skipped over.
+    pub extensible: bool,
+}
+
+impl ContainerAttrs {
+    pub fn parse(input: &DeriveInput) -> Result<Self> {
+        let mut attrs = Self::default();
+
+        for meta in protocol_metas(&input.attrs)? {
+            match meta {
+                Meta::Path(ref path) if path.is_ident("extensible") => {
+                    set_flag(&mut attrs.extensible, &meta)?;
+
+                    if !matches!(input.data, Data::Struct(_)) {
+                        let msg = "only structs can be `extensible`";
+                        return Err(Error::new(meta.span(), msg));
+                    }
+                }
+                meta => return Err(unrecognized(&meta)),
+            }
+        }
+
+        match input.data {
+            Data::Struct(ref data) => check_since(&data.fields, attrs.extensible)?,
+            Data::Enum(ref data) => {
+                for v in data.variants.iter() {
+                    check_since(&v.fields, false)?;
+                }
+            }
+            Data::Union(_) => {}
+        }
+
+        Ok(attrs)
+    }
+}
+
+/// Checks that fields marked `since` trail the rest in version order.
+fn check_since(fields: &Fields, extensible: bool) -> Result<()> {
+    let mut last: Option<u32> = None;
+
+    for f in fields.iter() {
+        let attrs = FieldAttrs::parse(f)?;
+
+        match (attrs.since, attrs.since_span) {
+            (Some(_), Some(span)) if !extensible => {
+                let msg = "`since` requires #[protocol(extensible)] on the struct";
+                return Err(Error::new(span, msg));
+            }
+            (Some(since), Some(span)) => {
+                if last.is_some_and(|last| since < last) {
+                    let msg = "fields must be in order of the version they were added `since`";
+                    return Err(Error::new(span, msg));
+                }
+
+                last = Some(since);
+            }
+            _ if last.is_some() && !attrs.skip => {
+                let msg = "fields without `since` must come before those with it";
+                let span = match f.ident {
+                    Some(ref ident) => ident.span(),
+                    None => f.ty.span(),
+                };
+
+                return Err(Error::new(span, msg));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Options set on a field with `#[protocol(...)]`.
+#[derive(Default)]
+pub struct FieldAttrs {
+    /// Encode the field as a `protocol::Var`.
+    pub var: bool,
+
+    /// Leave the field off the wire entirely.
+    pub skip: bool,
+
+    /// Function used to fill in a skipped or missing field instead of
+    /// `Default`.
+    pub default: Option<Path>,
+
+    /// Module providing `encode` and `decode` functions for the field.
+    pub with: Option<Path>,
+
+    /// Version in which the field was added to an extensible struct.
+    pub since: Option<u32>,
+
+    /// Where `since` was set, for reporting errors.
+    pub since_span: Option<Span>,
+}
+
+impl FieldAttrs {
+    pub fn parse(field: &Field) -> Result<Self> {
+        let mut attrs = Self::default();
+        let mut var_span = None;
+        let mut default_span = None;
+        let mut with_span = None;
+
+        for meta in protocol_metas(&field.attrs)? {
+            match meta {
+                Meta::Path(ref path) if path.is_ident("var") => {
+                    set_flag(&mut attrs.var, &meta)?;
+                    var_span = Some(meta.span());
+                }
+                Meta::Path(ref path) if path.is_ident("skip") => {
+                    set_flag(&mut attrs.skip, &meta)?;
+                }
+                Meta::NameValue(ref nv) if nv.path.is_ident("default") => {
+                    set_option(&mut attrs.default, &meta, parse_path(&nv.lit)?)?;
+                    default_span = Some(meta.span());
+                }
+                Meta::NameValue(ref nv) if nv.path.is_ident("with") => {
+                    set_option(&mut attrs.with, &meta, parse_path(&nv.lit)?)?;
+                    with_span = Some(meta.span());
+                }
+                Meta::NameValue(ref nv) if nv.path.is_ident("since") => match nv.lit {
+                    Lit::Int(ref int) => {
+                        let since = int
+                            .base10_parse()
+                            .map_err(|_| Error::new(int.span(), "version must fit in a u32"))?;
+                        set_option(&mut attrs.since, &meta, since)?;
+                        attrs.since_span = Some(meta.span());
+                    }
+                    ref lit => return Err(Error::new(lit.span(), "expected integer version")),
+                },
+                meta => return Err(unrecognized(&meta)),
+            }
+        }
+
+        if let (Some(_), Some(span)) = (var_span, with_span) {
+            let msg = "`var` and `with` cannot be used together";
+            return Err(Error::new(span, msg));
+        }
+
+        if let (true, Some(span)) = (attrs.skip, var_span.or(with_span)) {
+            let msg = "skipped fields are not encoded, so `var` and `with` do not apply";
+            return Err(Error::new(span, msg));
+        }
+
+        if let (Some(span), true) = (var_span, is_bool(&field.ty)) {
+            let msg = "`var` does not apply to `bool` fields";
+            return Err(Error::new(span, msg));
+        }
+
+        if let (true, Some(span)) = (attrs.skip, attrs.since_span) {
+            let msg = "skipped fields are not encoded, so `since` does not apply";
+            return Err(Error::new(span, msg));
+        }
+
+        if let (false, None, Some(span)) = (attrs.skip, attrs.since, default_span) {
+            return Err(Error::new(span, "`default` requires `skip` or `since`"));
+        }
+
+        Ok(attrs)
+    }
+}
+
+/// Whether `ty` is spelled as a plain `bool`.
+pub fn is_bool(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => ty.qself.is_none() && ty.path.is_ident("bool"),
+        _ => false,
+    }
+}
+
+/// Options set on an enum variant with `#[protocol(...)]`.
+#[derive(Default)]
+pub struct VariantAttrs {
+    /// Explicit wire tag of this variant.
+    pub tag: Option<u16>,
+}
+
+impl VariantAttrs {
+    pub fn parse(variant: &syn::Variant) -> Result<Self> {
+        let mut attrs = Self::default();
+
+        for meta in protocol_metas(&variant.attrs)? {
+            match meta {
+                Meta::NameValue(ref nv) if nv.path.is_ident("tag") => match nv.lit {
+                    Lit::Int(ref int) => {
+                        let tag = int
+                            .base10_parse()
+                            .map_err(|_| Error::new(int.span(), "variant tag must fit in a u16"))?;
+                        set_option(&mut attrs.tag, &meta, tag)?;
+                    }
+                    ref lit => return Err(Error::new(lit.span(), "expected integer tag")),
+                },
+                meta => return Err(unrecognized(&meta)),
+            }
+        }
+
+        Ok(attrs)
+    }
+}
+
+/// Collects the items of every `#[protocol(...)]` attribute.
+fn protocol_metas(attrs: &[Attribute]) -> Result<Vec<Meta>> {
+    let mut metas = Vec::new();
+
+    for attr in attrs.iter() {
+        if !attr.path.is_ident("protocol") {
+            continue;
+        }
+
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new(meta.span(), "expected #[protocol(...)]")),
+        };
+
+        for nested in list.nested.into_iter() {
+            match nested {
+                NestedMeta::Meta(meta) => metas.push(meta),
+                NestedMeta::Lit(lit) => {
+                    return Err(Error::new(lit.span(), "expected a protocol attribute"))
+                }
+            }
+        }
+    }
+
+    Ok(metas)
+}
+
+fn set_flag(flag: &mut bool, meta: &Meta) -> Result<()> {
+    if *flag {
+        return Err(duplicate(meta));
+    }
+
+    *flag = true;
+    Ok(())
+}
+
+fn set_option<T>(option: &mut Option<T>, meta: &Meta, value: T) -> Result<()> {
+    if option.is_some() {
+        return Err(duplicate(meta));
+    }
+
+    *option = Some(value);
+    Ok(())
+}
+
+fn parse_path(lit: &Lit) -> Result<Path> {
+    match lit {
+        Lit::Str(ref lit) => lit
+            .parse()
+            .map_err(|_| Error::new(lit.span(), format!("invalid path: {:?}", lit.value()))),
+        lit => Err(Error::new(
+            lit.span(),
+            "expected a path in a string literal",
+        )),
+    }
+}
+
+fn duplicate(meta: &Meta) -> Error {
+    let name = path_name(meta.path());
+    Error::new(
+        meta.span(),
+        format!("duplicate protocol attribute `{}`", name),
+    )
+}
+
+fn unrecognized(meta: &Meta) -> Error {
+    let name = path_name(meta.path());
+    Error::new(
+        meta.span(),
+        format!("unrecognized protocol attribute `{}`", name),
+    )
+}
+
+fn path_name(path: &Path) -> String {
+    quote::quote!(#path).to_string().replace(' ', "")
+}
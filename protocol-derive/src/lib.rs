@@ -1,74 +1,1169 @@
+use attr::{is_bool, ContainerAttrs, FieldAttrs, VariantAttrs};
 use proc_macro::TokenStream;
-use quote::{quote, quote_spanned};
-use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields};
+use proc_macro2::{TokenStream as TokenStream2, TokenTree};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, Data, DataEnum, DataStruct,
+    DataUnion, DeriveInput, Error, Field, Fields, Generics, Ident, Index, Lifetime, Lit, Meta,
+    MetaNameValue, Path, Result, Type, Variant,
+};
+
+mod attr;
 
 // with help from: https://github.com/dtolnay/syn/blob/master/examples/heapsize/heapsize_derive/src/lib.rs
 
-#[proc_macro_derive(Encode)]
+/// Derives `protocol::Encode`, encoding fields in declaration order.
+///
+/// Plain `bool` fields are packed together into a leading `protocol::BitSet`
+/// rather than taking up a byte each, except for those added `since` a
+/// version, which would change the size of the bitset.
+///
+/// Fields accept `#[protocol(...)]` attributes:
+/// - `var`: encode an integer field as a `protocol::Var`.
+/// - `skip`: leave the field off the wire.
+/// - `default = "path"`: a function to call instead of `Default::default`
+///   for a field that isn't decoded.
+/// - `with = "module"`: encode with `module::encode(&value, writer)` instead.
+/// - `since = N`: in an extensible struct, the version the field was added
+///   in. Older peers decode without it, and it takes its default (or
+///   `default = "path"`) when decoded from them.
+///
+/// Structs marked `#[protocol(extensible)]` are prefixed with the length of
+/// their fields, so that fields can be added to the end of them `since` a
+/// later version without breaking older peers, which skip over them.
+///
+/// Enum variants are prefixed by a `Var<u16>` tag, which can be pinned with
+/// `#[protocol(tag = N)]`.
+///
+/// `encoded_len` is derived as the sum of the fields' lengths, except that
+/// `with` fields are measured by encoding them into a `protocol::LenCounter`.
+#[proc_macro_derive(Encode, attributes(protocol))]
 pub fn derive_encode(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `protocol::Decode`, the inverse of `#[derive(Encode)]`.
+///
+/// Accepts the same attributes as `Encode`, with a `with` module providing
+/// `module::decode(ctx)`, where `ctx` is a `protocol::DecodeContext`.
+///
+/// Errors are annotated with the field they occurred in.
+///
+/// Every derived type counts as a level of nesting towards
+/// `protocol::Limits::max_depth`.
+#[proc_macro_derive(Decode, attributes(protocol))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `protocol::DecodeRef`, decoding the same wire format as
+/// `#[derive(Decode)]` but letting fields borrow from the input buffer.
+///
+/// Every lifetime parameter of the type is outlived by the buffer, so
+/// `&'a str` and `&'a [u8]` fields are decoded without copying. Fields
+/// accept the same attributes as `Decode`, except that a `with` module
+/// provides `module::decode_ref(ctx)` instead.
+#[proc_macro_derive(DecodeRef, attributes(protocol))]
+pub fn derive_decode_ref(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode_ref(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `protocol::AsyncDecode`, decoding the same wire format as
+/// `#[derive(Decode)]` from a tokio `AsyncRead`.
+///
+/// Fields accept the same attributes as `Decode`, except that a `with` module
+/// provides `async fn decode_async(ctx)` instead.
+///
+/// The future returned for each derived type is boxed, so that types can
+/// contain themselves. Encoding needs no derive, since `protocol::AsyncEncode`
+/// is implemented for everything that implements `Encode`.
+///
+/// This needs the `tokio` feature of `protocol`.
+#[proc_macro_derive(AsyncDecode, attributes(protocol))]
+pub fn derive_async_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_async_decode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `protocol::Describe`, adding the type's wire format to a
+/// `protocol::Schema` along with that of every type it contains.
+///
+/// Doc comments on the type, its variants and its fields are included in the
+/// schema. Accepts the same attributes as `Encode`, and fields encoded `with`
+/// a custom module are described by their Rust type.
+#[proc_macro_derive(Describe, attributes(protocol))]
+pub fn derive_describe(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_describe(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `proptest::arbitrary::Arbitrary`, generating values that survive
+/// a round trip through `Encode` and `Decode`, for use with
+/// `protocol::testing::check_roundtrip` and `check_truncated`.
+///
+/// Accepts the same attributes as `Encode`. Skipped fields always take their
+/// default, since that's what they decode as, while fields encoded `with` a
+/// custom module are generated from their Rust type and so must round-trip
+/// through it whatever their value.
+///
+/// This needs the `proptest` feature of `protocol`, so it's usually derived
+/// only for tests, with `#[cfg_attr(test, derive(Arbitrary))]`.
+#[proc_macro_derive(Arbitrary, attributes(protocol))]
+pub fn derive_arbitrary(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_arbitrary(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Which of the decoding traits is being derived.
+#[derive(Clone, Copy)]
+enum Decoder {
+    /// `protocol::Decode`.
+    Owned,
+
+    /// `protocol::DecodeRef`, borrowing from the buffer for `'__de`.
+    Borrowed,
+
+    /// `protocol::AsyncDecode`, awaiting each field in turn.
+    Async,
+}
+
+impl Decoder {
+    /// A call decoding a `ty` out of `ctx`.
+    fn decode(self, ty: &Type) -> TokenStream2 {
+        match self {
+            Decoder::Owned => quote! {
+                <#ty as ::protocol::Decode>::decode_with(ctx)
+            },
+            Decoder::Borrowed => quote! {
+                <#ty as ::protocol::DecodeRef<'__de>>::decode_ref_with(ctx)
+            },
+            Decoder::Async => quote! {
+                <#ty as ::protocol::AsyncDecode>::decode_async_with(ctx).await
+            },
+        }
+    }
+
+    /// A call decoding a field out of `ctx` with a `with` module.
+    fn decode_using(self, with: &Path) -> TokenStream2 {
+        match self {
+            Decoder::Owned => quote! { #with::decode(ctx) },
+            Decoder::Borrowed => quote! { #with::decode_ref(ctx) },
+            Decoder::Async => quote! { #with::decode_async(ctx).await },
+        }
+    }
+
+    /// Decodes `body` within a body of `len` bytes, as in an extensible
+    /// struct.
+    fn bounded(self, body: TokenStream2) -> TokenStream2 {
+        match self {
+            Decoder::Owned | Decoder::Borrowed => quote! {
+                ctx.bounded(len, |ctx| {
+                    #body
+                })
+            },
+            // closures can't await, so the body is entered and left by hand
+            Decoder::Async => quote! {
+                async {
+                    let body = ctx.enter_body(len)?;
+                    let result: ::protocol::Result<Self> = async { #body }.await;
+                    ctx.leave_body_async(body, result).await
+                }
+                .await
+            },
+        }
+    }
+}
+
+fn expand_encode(input: DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttrs::parse(&input)?;
     let name = input.ident;
+    let generics = add_trait_bounds(
+        input.generics,
+        &input.data,
+        parse_quote!(::protocol::Encode),
+        false,
+    )?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let data = &input.data;
 
-    let encode_members = match data {
-        Data::Struct(ref data) => match data.fields {
-            Fields::Named(ref fields) => {
-                let recurse = fields.named.iter().map(|f| {
-                    let name = &f.ident;
-                    quote_spanned! { f.span() =>
-                        ::protocol::Encode::encode(&self.#name, writer)?;
-                    }
-                });
-                quote! { #(#recurse)* }
+    let (encode_members, encoded_len) = match data {
+        Data::Struct(ref data) => {
+            let bindings = match data.fields {
+                Fields::Named(ref fields) => fields
+                    .named
+                    .iter()
+                    .map(|f| {
+                        let name = &f.ident;
+                        quote_spanned! { f.span() => &self.#name }
+                    })
+                    .collect(),
+                Fields::Unnamed(ref fields) => fields
+                    .unnamed
+                    .iter()
+                    .enumerate()
+                    .map(|(index, f)| {
+                        let index = Index::from(index);
+                        quote_spanned! { f.span() => &self.#index }
+                    })
+                    .collect(),
+                Fields::Unit => Vec::new(),
+            };
+            let ty_name = name.to_string();
+            let encode = encode_fields(&ty_name, &data.fields, &bindings)?;
+            let len = fields_len(&data.fields, &bindings)?;
+
+            if container.extensible {
+                extensible_encode(&ty_name, encode, len)
+            } else {
+                (encode, len)
             }
-            _ => unimplemented!(),
-        },
-        _ => unimplemented!(),
+        }
+        Data::Enum(ref data) if data.variants.is_empty() => {
+            return Err(Error::new_spanned(
+                &name,
+                "enums without variants have no values to encode",
+            ));
+        }
+        Data::Enum(ref data) => (encode_enum(&name, data)?, enum_len(data)?),
+        Data::Union(ref data) => return Err(unsupported_union(data)),
     };
 
-    let expanded = quote! {
-        impl ::protocol::Encode for #name {
-            fn encode(&self, writer: &mut impl ::std::io::Write) -> ::std::io::Result<()> {
+    Ok(quote! {
+        impl #impl_generics ::protocol::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, writer: &mut impl ::std::io::Write) -> ::protocol::Result<()> {
                 #encode_members
                 Ok(())
             }
+
+            fn encoded_len(&self) -> usize {
+                #encoded_len
+            }
         }
+    })
+}
+
+fn expand_decode(input: DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttrs::parse(&input)?;
+    let name = input.ident;
+    let generics = add_trait_bounds(
+        input.generics,
+        &input.data,
+        parse_quote!(::protocol::Decode),
+        true,
+    )?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let data = &input.data;
+
+    let decode_body = match data {
+        Data::Struct(ref data) => decode_struct(Decoder::Owned, container.extensible, &name, data)?,
+        Data::Enum(ref data) => decode_enum(Decoder::Owned, &name, data)?,
+        Data::Union(ref data) => return Err(unsupported_union(data)),
     };
 
-    expanded.into()
+    Ok(quote! {
+        impl #impl_generics ::protocol::Decode for #name #ty_generics #where_clause {
+            fn decode_with(
+                ctx: &mut ::protocol::DecodeContext<impl ::std::io::Read>,
+            ) -> ::protocol::Result<Self> {
+                ctx.nested(|ctx| {
+                    #decode_body
+                })
+            }
+        }
+    })
 }
 
-#[proc_macro_derive(Decode)]
-pub fn derive_decode(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as DeriveInput);
+fn expand_decode_ref(input: DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttrs::parse(&input)?;
+    let name = input.ident;
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let de = Lifetime::new("'__de", proc_macro2::Span::call_site());
+    let mut generics = add_trait_bounds(
+        input.generics.clone(),
+        &input.data,
+        parse_quote!(::protocol::DecodeRef<#de>),
+        true,
+    )?;
+
+    let lifetimes: Vec<_> = generics.lifetimes().map(|l| l.lifetime.clone()).collect();
+    generics.params.insert(0, parse_quote!(#de));
+    let where_clause = generics.make_where_clause();
+    for lifetime in lifetimes {
+        where_clause.predicates.push(parse_quote!(#de: #lifetime));
+    }
+
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let data = &input.data;
+
+    let decode_body = match data {
+        Data::Struct(ref data) => {
+            decode_struct(Decoder::Borrowed, container.extensible, &name, data)?
+        }
+        Data::Enum(ref data) => decode_enum(Decoder::Borrowed, &name, data)?,
+        Data::Union(ref data) => return Err(unsupported_union(data)),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::protocol::DecodeRef<#de> for #name #ty_generics #where_clause {
+            fn decode_ref_with(
+                ctx: &mut ::protocol::DecodeContext<&#de [u8]>,
+            ) -> ::protocol::Result<Self> {
+                ctx.nested(|ctx| {
+                    #decode_body
+                })
+            }
+        }
+    })
+}
+
+fn expand_async_decode(input: DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttrs::parse(&input)?;
     let name = input.ident;
+    let generics = add_trait_bounds(
+        input.generics,
+        &input.data,
+        parse_quote!(::protocol::AsyncDecode),
+        true,
+    )?;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let data = &input.data;
 
-    let decode_members = match data {
-        Data::Struct(ref data) => match data.fields {
-            Fields::Named(ref fields) => {
-                let recurse = fields.named.iter().map(|f| {
-                    let name = &f.ident;
-                    quote_spanned! { f.span() =>
-                        #name: ::protocol::Decode::decode(reader)?,
+    let decode_body = match data {
+        Data::Struct(ref data) => decode_struct(Decoder::Async, container.extensible, &name, data)?,
+        Data::Enum(ref data) => decode_enum(Decoder::Async, &name, data)?,
+        Data::Union(ref data) => return Err(unsupported_union(data)),
+    };
+
+    let read = quote! { ::protocol::tokio::io::AsyncRead };
+    let future = quote! { ::std::future::Future<Output = ::protocol::Result<Self>> };
+
+    Ok(quote! {
+        impl #impl_generics ::protocol::AsyncDecode for #name #ty_generics #where_clause {
+            fn decode_async_with<R: #read + ::std::marker::Unpin + ::std::marker::Send>(
+                ctx: &mut ::protocol::DecodeContext<R>,
+            ) -> impl #future + ::std::marker::Send {
+                let future: ::std::pin::Pin<::std::boxed::Box<dyn #future + ::std::marker::Send + '_>> =
+                    ::std::boxed::Box::pin(async move {
+                        ctx.enter()?;
+                        let result: ::protocol::Result<Self> = async { #decode_body }.await;
+                        ctx.leave();
+                        result
+                    });
+
+                future
+            }
+        }
+    })
+}
+
+fn expand_describe(input: DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttrs::parse(&input)?;
+    let name = input.ident;
+    let doc = doc_string(&input.attrs);
+    let params: Vec<_> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let mut generics = add_trait_bounds(
+        input.generics,
+        &input.data,
+        parse_quote!(::protocol::Describe),
+        false,
+    )?;
+
+    // the type's name includes those of its parameters
+    let where_clause = generics.make_where_clause();
+    for param in params.iter() {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::protocol::Describe));
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let type_name = if params.is_empty() {
+        let name = name.to_string();
+        quote! { ::std::string::String::from(#name) }
+    } else {
+        let format = format!("{}<{{}}>", name);
+        quote! {
+            let params: ::std::vec::Vec<::std::string::String> =
+                ::std::vec![#(<#params as ::protocol::Describe>::type_name()),*];
+            ::std::format!(#format, params.join(", "))
+        }
+    };
+
+    let (kind, contained) = match input.data {
+        Data::Struct(ref data) => {
+            let extensible = container.extensible;
+            let (fields, contained) = describe_fields(&data.fields)?;
+            let kind = quote! {
+                ::protocol::TypeKind::Struct {
+                    extensible: #extensible,
+                    fields: #fields,
+                }
+            };
+
+            (kind, contained)
+        }
+        Data::Enum(ref data) => {
+            let tags = variant_tags(data)?;
+            let mut variants = Vec::with_capacity(data.variants.len());
+            let mut contained = Vec::new();
+
+            for (v, tag) in data.variants.iter().zip(tags) {
+                let name = v.ident.to_string();
+                let doc = doc_string(&v.attrs);
+                let (fields, variant_contained) = describe_fields(&v.fields)?;
+                contained.extend(variant_contained);
+                variants.push(quote! {
+                    ::protocol::VariantDef {
+                        name: ::std::string::String::from(#name),
+                        doc: #doc,
+                        tag: #tag,
+                        fields: #fields,
                     }
                 });
-                quote! { #(#recurse)* }
             }
-            _ => unimplemented!(),
-        },
-        _ => unimplemented!(),
+
+            let kind = quote! {
+                ::protocol::TypeKind::Enum {
+                    variants: ::std::vec![#(#variants),*],
+                }
+            };
+
+            (kind, contained)
+        }
+        Data::Union(ref data) => return Err(unsupported_union(data)),
     };
 
-    let expanded = quote! {
-        impl ::protocol::Decode for #name {
-            fn decode(reader: &mut impl ::std::io::Read) -> ::std::io::Result<Self> {
-                Ok(Self {
-                    #decode_members
-                })
+    Ok(quote! {
+        impl #impl_generics ::protocol::Describe for #name #ty_generics #where_clause {
+            fn type_name() -> ::std::string::String {
+                #type_name
+            }
+
+            fn define(schema: &mut ::protocol::Schema) {
+                let def = ::protocol::TypeDef {
+                    name: Self::type_name(),
+                    doc: #doc,
+                    kind: #kind,
+                };
+
+                if schema.insert(def) {
+                    #(<#contained as ::protocol::Describe>::define(schema);)*
+                }
             }
         }
+    })
+}
+
+fn expand_arbitrary(input: DeriveInput) -> Result<TokenStream2> {
+    ContainerAttrs::parse(&input)?;
+    let name = input.ident;
+    let proptest = quote! { ::protocol::testing::proptest };
+
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for (f, attrs) in generic_fields(&input.generics, &input.data)? {
+        let ty = &f.ty;
+        if attrs.skip {
+            if attrs.default.is_none() {
+                where_clause
+                    .predicates
+                    .push(parse_quote!(#ty: ::std::default::Default));
+            }
+            continue;
+        }
+
+        // the strategy is boxed, so those it's built from must be 'static too
+        where_clause.predicates.push(parse_quote! {
+            #ty: #proptest::arbitrary::Arbitrary
+        });
+        where_clause.predicates.push(parse_quote! {
+            <#ty as #proptest::arbitrary::Arbitrary>::Strategy: 'static
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let strategy = match input.data {
+        Data::Struct(ref data) => arbitrary_fields(quote! { Self }, &data.fields)?,
+        Data::Enum(ref data) if data.variants.is_empty() => {
+            return Err(Error::new_spanned(
+                &name,
+                "enums without variants have no values to generate",
+            ));
+        }
+        Data::Enum(ref data) => {
+            let mut variants = Vec::with_capacity(data.variants.len());
+            for v in data.variants.iter() {
+                let ident = &v.ident;
+                variants.push(arbitrary_fields(quote! { Self::#ident }, &v.fields)?);
+            }
+
+            quote! {
+                #proptest::strategy::Strategy::boxed(
+                    #proptest::strategy::Union::new(::std::vec![#(#variants),*])
+                )
+            }
+        }
+        Data::Union(ref data) => return Err(unsupported_union(data)),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #proptest::arbitrary::Arbitrary for #name #ty_generics #where_clause {
+            type Parameters = ();
+            type Strategy = #proptest::strategy::BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                #strategy
+            }
+        }
+    })
+}
+
+/// A boxed strategy generating each encoded field and then building them
+/// into `constructor`, with skipped fields taking their defaults.
+fn arbitrary_fields(constructor: TokenStream2, fields: &Fields) -> Result<TokenStream2> {
+    let proptest = quote! { ::protocol::testing::proptest };
+    let locals: Vec<_> = (0..fields.len())
+        .map(|index| format_ident!("field_{}", index))
+        .collect();
+
+    // strategies are nested in pairs, since tuples of them only go up to 12
+    let mut strategy = quote! { #proptest::strategy::Just(()) };
+    let mut pattern = quote! { () };
+    let mut defaults = Vec::new();
+
+    for (f, local) in fields.iter().zip(locals.iter()).rev() {
+        let attrs = FieldAttrs::parse(f)?;
+        let ty = &f.ty;
+
+        if attrs.skip {
+            let default = default_value(&attrs, ty);
+            defaults.push(quote! { let #local: #ty = #default; });
+        } else {
+            strategy = quote_spanned! { ty.span() =>
+                (#proptest::arbitrary::any::<#ty>(), #strategy)
+            };
+            pattern = quote! { (#local, #pattern) };
+        }
+    }
+
+    let build = match fields {
+        Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { #constructor { #(#names: #locals),* } }
+        }
+        Fields::Unnamed(_) => quote! { #constructor(#(#locals),*) },
+        Fields::Unit => quote! { #constructor },
     };
 
-    expanded.into()
+    Ok(quote! {
+        #proptest::strategy::Strategy::boxed(#proptest::strategy::Strategy::prop_map(
+            #strategy,
+            |#pattern| {
+                #(#defaults)*
+                #build
+            },
+        ))
+    })
+}
+
+/// Describes the fields that are encoded, returning an expression building a
+/// `Vec` of `protocol::FieldDef` along with the types that the schema
+/// needs definitions of.
+fn describe_fields(fields: &Fields) -> Result<(TokenStream2, Vec<Type>)> {
+    let mut packed = 0usize;
+    let mut field_defs = Vec::with_capacity(fields.len());
+    let mut contained = Vec::with_capacity(fields.len());
+
+    for (index, f) in fields.iter().enumerate() {
+        let attrs = FieldAttrs::parse(f)?;
+        let ty = &f.ty;
+
+        if attrs.skip {
+            continue;
+        }
+
+        let name = match f.ident {
+            Some(ref ident) => ident.to_string(),
+            None => index.to_string(),
+        };
+
+        let bit = if is_packed_bool(f, &attrs) {
+            let bit = packed;
+            packed += 1;
+            quote! { ::std::option::Option::Some(#bit) }
+        } else {
+            quote! { ::std::option::Option::None }
+        };
+
+        let (ty_name, with) = match attrs.with {
+            Some(ref with) => {
+                let ty = tokens_string(quote!(#ty));
+                let with = tokens_string(quote!(#with));
+                (
+                    quote! { ::std::string::String::from(#ty) },
+                    quote! { ::std::option::Option::Some(::std::string::String::from(#with)) },
+                )
+            }
+            None => {
+                let wire_ty: Type = if attrs.var {
+                    parse_quote!(::protocol::Var<#ty>)
+                } else {
+                    ty.clone()
+                };
+
+                let ty_name = quote! { <#wire_ty as ::protocol::Describe>::type_name() };
+                contained.push(wire_ty);
+                (ty_name, quote! { ::std::option::Option::None })
+            }
+        };
+
+        let since = match attrs.since {
+            Some(since) => quote! { ::std::option::Option::Some(#since) },
+            None => quote! { ::std::option::Option::None },
+        };
+
+        let doc = doc_string(&f.attrs);
+        field_defs.push(quote! {
+            ::protocol::FieldDef {
+                name: ::std::string::String::from(#name),
+                doc: #doc,
+                ty: #ty_name,
+                bit: #bit,
+                since: #since,
+                with: #with,
+            }
+        });
+    }
+
+    Ok((quote! { ::std::vec![#(#field_defs),*] }, contained))
+}
+
+/// An expression for the doc comment on an item, if it has one.
+fn doc_string(attrs: &[Attribute]) -> TokenStream2 {
+    let lines: Vec<_> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(MetaNameValue {
+                lit: Lit::Str(lit), ..
+            })) => Some(lit.value().trim().to_string()),
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if lines.is_empty() {
+        quote! { ::std::option::Option::None }
+    } else {
+        let doc = lines.join(" ");
+        quote! { ::std::option::Option::Some(::std::string::String::from(#doc)) }
+    }
+}
+
+/// Renders tokens like a type or path without the spaces between them.
+fn tokens_string(tokens: TokenStream2) -> String {
+    tokens
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(":: ", "::")
+        .replace(" <", "<")
+        .replace("< ", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+}
+
+fn unsupported_union(data: &DataUnion) -> Error {
+    let msg = "unions cannot be encoded because the active field is unknown";
+    Error::new(data.union_token.span(), msg)
+}
+
+/// Bounds the types of the fields that mention a type parameter, rather than
+/// the parameters themselves, so that a parameter only used by a skipped or
+/// `with` field doesn't need to implement `bound`.
+///
+/// Encoded fields are bounded on `bound`, on `protocol::Var` of their type
+/// for `var` fields. When `decodes`, fields filled in by `Default` when
+/// they aren't decoded are bounded on it too.
+fn add_trait_bounds(
+    mut generics: Generics,
+    data: &Data,
+    bound: Path,
+    decodes: bool,
+) -> Result<Generics> {
+    let fields = generic_fields(&generics, data)?;
+    let where_clause = generics.make_where_clause();
+
+    for (f, attrs) in fields {
+        let ty = &f.ty;
+        if decodes && (attrs.skip || attrs.since.is_some()) && attrs.default.is_none() {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::std::default::Default));
+        }
+
+        if attrs.skip || attrs.with.is_some() {
+            continue;
+        }
+
+        if attrs.var {
+            where_clause
+                .predicates
+                .push(parse_quote!(::protocol::Var<#ty>: #bound));
+        } else {
+            where_clause.predicates.push(parse_quote!(#ty: #bound));
+        }
+    }
+
+    Ok(generics)
+}
+
+/// The fields of a struct or enum whose types mention a type parameter.
+fn generic_fields<'a>(generics: &Generics, data: &'a Data) -> Result<Vec<(&'a Field, FieldAttrs)>> {
+    let params: Vec<_> = generics.type_params().map(|p| &p.ident).collect();
+    let fields: Vec<&Field> = match *data {
+        Data::Struct(ref data) => data.fields.iter().collect(),
+        Data::Enum(ref data) => data.variants.iter().flat_map(|v| v.fields.iter()).collect(),
+        Data::Union(_) => Vec::new(),
+    };
+
+    let mut generic = Vec::new();
+    for f in fields {
+        if mentions_any(f.ty.to_token_stream(), &params) {
+            generic.push((f, FieldAttrs::parse(f)?));
+        }
+    }
+
+    Ok(generic)
+}
+
+/// Whether `tokens` contain any of `idents`, including within groups.
+fn mentions_any(tokens: TokenStream2, idents: &[&Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ref ident) => idents.contains(&ident),
+        TokenTree::Group(ref group) => mentions_any(group.stream(), idents),
+        _ => false,
+    })
+}
+
+/// Whether a field is a plain `bool` that is packed into the leading bitset.
+fn is_packed_bool(field: &Field, attrs: &FieldAttrs) -> bool {
+    is_bool(&field.ty) && !attrs.skip && attrs.with.is_none() && attrs.since.is_none()
+}
+
+/// A closure adding `field` of `ty_name` to the path of an error.
+///
+/// `with` modules may return any error convertible to `protocol::Error`.
+fn in_field(ty_name: &str, field: &Field, index: usize) -> TokenStream2 {
+    let field_name = match field.ident {
+        Some(ref ident) => ident.to_string(),
+        None => index.to_string(),
+    };
+
+    quote! {
+        |err| ::protocol::Error::from(err).in_field(#ty_name, #field_name)
+    }
+}
+
+/// The size in bytes of the bitset holding `count` packed bools.
+fn bitset_len(count: usize) -> usize {
+    count.div_ceil(8)
+}
+
+/// Encodes each field in order, given an expression evaluating to a
+/// reference to each of them.
+///
+/// Plain `bool` fields are packed into a `protocol::BitSet` that precedes
+/// the rest of the fields, in the order that they were declared.
+fn encode_fields(
+    ty_name: &str,
+    fields: &Fields,
+    bindings: &[TokenStream2],
+) -> Result<TokenStream2> {
+    let mut packed = Vec::new();
+    let mut encode_members = Vec::with_capacity(fields.len());
+
+    for (index, (f, binding)) in fields.iter().zip(bindings).enumerate() {
+        let attrs = FieldAttrs::parse(f)?;
+        let ty = &f.ty;
+        let in_field = in_field(ty_name, f, index);
+
+        if is_packed_bool(f, &attrs) {
+            let index = packed.len();
+            packed.push(quote_spanned! { f.span() =>
+                let value: &bool = #binding;
+                bools.set(#index, *value);
+            });
+        } else if attrs.skip {
+            continue;
+        } else if let Some(with) = attrs.with {
+            encode_members.push(quote_spanned! { with.span() =>
+                #with::encode(#binding, writer).map_err(#in_field)?;
+            });
+        } else if attrs.var {
+            encode_members.push(quote_spanned! { ty.span() =>
+                let value: &#ty = #binding;
+                ::protocol::Encode::encode(&::protocol::Var::<#ty>(*value), writer)
+                    .map_err(#in_field)?;
+            });
+        } else {
+            encode_members.push(quote_spanned! { f.span() =>
+                ::protocol::Encode::encode(#binding, writer).map_err(#in_field)?;
+            });
+        }
+    }
+
+    let encode_bools = if packed.is_empty() {
+        quote! {}
+    } else {
+        let len = bitset_len(packed.len());
+        quote! {
+            let mut bools = ::protocol::BitSet::<#len>::new();
+            #(#packed)*
+            ::protocol::Encode::encode(&bools, writer).map_err(|err| err.in_type(#ty_name))?;
+        }
+    };
+
+    Ok(quote! {
+        #encode_bools
+        #(#encode_members)*
+    })
+}
+
+/// Sums up the encoded size of each field, given an expression evaluating
+/// to a reference to each of them.
+fn fields_len(fields: &Fields, bindings: &[TokenStream2]) -> Result<TokenStream2> {
+    let mut packed = 0;
+    let mut field_lens = Vec::with_capacity(fields.len());
+
+    for (f, binding) in fields.iter().zip(bindings) {
+        let attrs = FieldAttrs::parse(f)?;
+        let ty = &f.ty;
+
+        if is_packed_bool(f, &attrs) {
+            packed += 1;
+        } else if attrs.skip {
+            continue;
+        } else if let Some(with) = attrs.with {
+            field_lens.push(quote_spanned! { with.span() =>
+                {
+                    let mut counter = ::protocol::LenCounter::default();
+                    let _ = #with::encode(#binding, &mut counter);
+                    counter.0
+                }
+            });
+        } else if attrs.var {
+            field_lens.push(quote_spanned! { ty.span() =>
+                {
+                    let value: &#ty = #binding;
+                    ::protocol::Encode::encoded_len(&::protocol::Var::<#ty>(*value))
+                }
+            });
+        } else {
+            field_lens.push(quote_spanned! { f.span() =>
+                ::protocol::Encode::encoded_len(#binding)
+            });
+        }
+    }
+
+    let bitset_len = bitset_len(packed);
+    Ok(quote! {
+        #bitset_len #(+ #field_lens)*
+    })
+}
+
+/// Prefixes the encoding of an extensible struct with the length of its
+/// fields, given the code to encode them and to compute their length.
+fn extensible_encode(
+    ty_name: &str,
+    encode_members: TokenStream2,
+    fields_len: TokenStream2,
+) -> (TokenStream2, TokenStream2) {
+    let encode = quote! {
+        let len = u32::try_from(#fields_len).map_err(|_| {
+            let kind = ::protocol::ErrorKind::LimitExceeded(::protocol::Limit::Bytes);
+            ::protocol::Error::new(kind).in_type(#ty_name)
+        })?;
+        ::protocol::Encode::encode(&::protocol::Var::<u32>(len), writer)
+            .map_err(|err| err.in_type(#ty_name))?;
+        #encode_members
+    };
+
+    let len = quote! {
+        {
+            let len = #fields_len;
+            ::protocol::Encode::encoded_len(&::protocol::Var::<u32>(len as u32)) + len
+        }
+    };
+
+    (encode, len)
+}
+
+fn decode_struct(
+    decoder: Decoder,
+    extensible: bool,
+    name: &Ident,
+    data: &DataStruct,
+) -> Result<TokenStream2> {
+    let ty_name = name.to_string();
+    let decode_body = decode_fields(decoder, &ty_name, quote!(Self), &data.fields)?;
+
+    if !extensible {
+        return Ok(decode_body);
+    }
+
+    // fields missing from the end of the body take their defaults, and any
+    // unknown fields that follow them are skipped
+    let decode_len = decoder.decode(&parse_quote!(::protocol::Var<u32>));
+    let bounded = decoder.bounded(decode_body);
+    Ok(quote! {
+        let len = #decode_len.map_err(|err| err.in_type(#ty_name))?.0 as usize;
+        #bounded.map_err(|err| err.in_type(#ty_name))
+    })
+}
+
+/// Decodes each field in order and then builds them into `constructor`.
+fn decode_fields(
+    decoder: Decoder,
+    ty_name: &str,
+    constructor: TokenStream2,
+    fields: &Fields,
+) -> Result<TokenStream2> {
+    let locals: Vec<_> = (0..fields.len())
+        .map(|index| format_ident!("field_{}", index))
+        .collect();
+
+    let mut packed = Vec::new();
+    let mut decode_members = Vec::with_capacity(fields.len());
+
+    for (index, (f, local)) in fields.iter().zip(locals.iter()).enumerate() {
+        let attrs = FieldAttrs::parse(f)?;
+        let ty = &f.ty;
+        let in_field = in_field(ty_name, f, index);
+
+        if is_packed_bool(f, &attrs) {
+            let index = packed.len();
+            packed.push(quote_spanned! { f.span() =>
+                let #local = bools.get(#index);
+            });
+            continue;
+        }
+
+        if attrs.skip {
+            let default = default_value(&attrs, ty);
+            decode_members.push(quote! { let #local: #ty = #default; });
+            continue;
+        }
+
+        let decode = if let Some(ref with) = attrs.with {
+            let decode = decoder.decode_using(with);
+            quote_spanned! { with.span() => #decode.map_err(#in_field)? }
+        } else if attrs.var {
+            let decode = decoder.decode(&parse_quote!(::protocol::Var<#ty>));
+            quote_spanned! { ty.span() => #decode.map_err(#in_field)?.0 }
+        } else {
+            let decode = decoder.decode(ty);
+            quote_spanned! { f.span() => #decode.map_err(#in_field)? }
+        };
+
+        if attrs.since.is_some() {
+            // older peers end the struct's body before fields added since
+            let default = default_value(&attrs, ty);
+            decode_members.push(quote_spanned! { f.span() =>
+                let #local: #ty = if ctx.remaining() > 0 { #decode } else { #default };
+            });
+        } else {
+            decode_members.push(quote_spanned! { f.span() =>
+                let #local: #ty = #decode;
+            });
+        }
+    }
+
+    let decode_bools = if packed.is_empty() {
+        quote! {}
+    } else {
+        let count = packed.len();
+        let len = bitset_len(count);
+        let decode = decoder.decode(&parse_quote!(::protocol::BitSet<#len>));
+        quote! {
+            let bools = #decode.map_err(|err| err.in_type(#ty_name))?;
+            bools.check_len(#count).map_err(|err| err.in_type(#ty_name))?;
+            #(#packed)*
+        }
+    };
+
+    let build = match fields {
+        Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { #constructor { #(#names: #locals),* } }
+        }
+        Fields::Unnamed(_) => quote! { #constructor(#(#locals),*) },
+        Fields::Unit => quote! { #constructor },
+    };
+
+    Ok(quote! {
+        #decode_bools
+        #(#decode_members)*
+        Ok(#build)
+    })
+}
+
+/// The value a field takes when it isn't decoded.
+fn default_value(attrs: &FieldAttrs, ty: &Type) -> TokenStream2 {
+    match attrs.default {
+        Some(ref default) => quote_spanned! { default.span() => #default() },
+        None => quote_spanned! { ty.span() => ::std::default::Default::default() },
+    }
+}
+
+/// Binds each field of a variant to `field_N` in a match pattern.
+fn variant_pattern(v: &Variant) -> (TokenStream2, Vec<TokenStream2>) {
+    let ident = &v.ident;
+    let bindings: Vec<_> = (0..v.fields.len())
+        .map(|index| {
+            let binding = format_ident!("field_{}", index);
+            quote! { #binding }
+        })
+        .collect();
+
+    let pattern = match v.fields {
+        Fields::Named(ref fields) => {
+            let names = fields.named.iter().map(|f| &f.ident);
+            quote! { Self::#ident { #(#names: #bindings),* } }
+        }
+        Fields::Unnamed(_) => quote! { Self::#ident(#(#bindings),*) },
+        Fields::Unit => quote! { Self::#ident },
+    };
+
+    (pattern, bindings)
+}
+
+fn encode_enum(name: &Ident, data: &DataEnum) -> Result<TokenStream2> {
+    let tags = variant_tags(data)?;
+    let mut arms = Vec::with_capacity(data.variants.len());
+
+    for (v, tag) in data.variants.iter().zip(tags) {
+        let ident = &v.ident;
+        let (pattern, bindings) = variant_pattern(v);
+        let ty_name = format!("{}::{}", name, ident);
+        let encode_members = encode_fields(&ty_name, &v.fields, &bindings)?;
+
+        arms.push(quote_spanned! { v.span() =>
+            #[allow(unused_variables)]
+            #pattern => {
+                ::protocol::Encode::encode(&::protocol::Var::<u16>(#tag), writer)
+                    .map_err(|err| err.in_type(#ty_name))?;
+                #encode_members
+            }
+        });
+    }
+
+    Ok(quote! {
+        match self {
+            #(#arms)*
+        }
+    })
+}
+
+fn enum_len(data: &DataEnum) -> Result<TokenStream2> {
+    let tags = variant_tags(data)?;
+    let mut arms = Vec::with_capacity(data.variants.len());
+
+    for (v, tag) in data.variants.iter().zip(tags) {
+        let (pattern, bindings) = variant_pattern(v);
+        let fields_len = fields_len(&v.fields, &bindings)?;
+
+        arms.push(quote_spanned! { v.span() =>
+            #[allow(unused_variables)]
+            #pattern => {
+                ::protocol::Encode::encoded_len(&::protocol::Var::<u16>(#tag)) + #fields_len
+            }
+        });
+    }
+
+    Ok(quote! {
+        match self {
+            #(#arms)*
+        }
+    })
+}
+
+fn decode_enum(decoder: Decoder, name: &Ident, data: &DataEnum) -> Result<TokenStream2> {
+    let tags = variant_tags(data)?;
+    let mut arms = Vec::with_capacity(data.variants.len());
+
+    for (v, tag) in data.variants.iter().zip(tags) {
+        let ident = &v.ident;
+        let ty_name = format!("{}::{}", name, ident);
+        let decode_body = decode_fields(decoder, &ty_name, quote!(Self::#ident), &v.fields)?;
+
+        arms.push(quote_spanned! { v.span() =>
+            #tag => {
+                #decode_body
+            }
+        });
+    }
+
+    let ty_name = name.to_string();
+    let decode_tag = decoder.decode(&parse_quote!(::protocol::Var<u16>));
+    Ok(quote! {
+        let tag = #decode_tag.map_err(|err| err.in_type(#ty_name))?.0;
+        match tag {
+            #(#arms)*
+            tag => {
+                let kind = ::protocol::ErrorKind::UnknownDiscriminant(tag.into());
+                Err(::protocol::Error::new(kind).in_type(#ty_name))
+            }
+        }
+    })
+}
+
+/// Assigns a wire tag to every variant of an enum.
+///
+/// Variants are numbered in declaration order, like Rust discriminants, but
+/// `#[protocol(tag = N)]` pins a variant to an explicit tag so that variants
+/// can be reordered or removed without changing the wire format. Variants
+/// following an explicitly-tagged one continue counting from its tag.
+fn variant_tags(data: &DataEnum) -> Result<Vec<u16>> {
+    let mut tags: Vec<u16> = Vec::with_capacity(data.variants.len());
+    let mut next: Option<u16> = Some(0);
+
+    for v in data.variants.iter() {
+        let tag = match VariantAttrs::parse(v)?.tag {
+            Some(tag) => tag,
+            None => next
+                .ok_or_else(|| Error::new(v.ident.span(), "implicit variant tag overflows u16"))?,
+        };
+
+        if tags.contains(&tag) {
+            let msg = format!("variant tag {} is already in use", tag);
+            return Err(Error::new(v.ident.span(), msg));
+        }
+
+        tags.push(tag);
+        next = tag.checked_add(1);
+    }
+
+    Ok(tags)
 }
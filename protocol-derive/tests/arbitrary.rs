@@ -0,0 +1,123 @@
+use proptest::prelude::*;
+use proptest::strategy::ValueTree;
+use proptest::test_runner::TestRunner;
+use protocol::testing::{check_roundtrip, check_truncated};
+use protocol::{BitSet, Var};
+use protocol_derive::{Arbitrary, Decode, Encode};
+
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+struct Flags {
+    #[protocol(var)]
+    id: u32,
+    pinned: bool,
+    #[protocol(skip, default = "default_color")]
+    color: String,
+    muted: bool,
+    tags: Vec<String>,
+    offset: Var<i64>,
+    raw: BitSet<2>,
+}
+
+fn default_color() -> String {
+    "red".to_string()
+}
+
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+#[protocol(extensible)]
+struct RoomInfo {
+    id: String,
+    #[protocol(skip)]
+    cached: Option<u32>,
+    #[protocol(since = 2)]
+    archived: bool,
+    #[protocol(since = 3)]
+    capacity: Option<u16>,
+}
+
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+enum Packet {
+    Ping,
+    RequestRoomInfo(String),
+    #[protocol(tag = 8)]
+    RoomInfo(RoomInfo),
+    Flags {
+        flags: Flags,
+        signed: Signed<(u8, char)>,
+    },
+}
+
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+struct Signed<T> {
+    payload: T,
+    signature: [u8; 4],
+}
+
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+struct Wide(
+    u8,
+    u16,
+    u32,
+    u64,
+    i8,
+    i16,
+    i32,
+    i64,
+    bool,
+    char,
+    String,
+    Vec<u8>,
+    #[protocol(skip)] (),
+    Option<bool>,
+);
+
+mod upper {
+    use protocol::{Decode, DecodeContext, Encode, Result};
+    use std::io::{Read, Write};
+
+    pub fn encode(value: &str, writer: &mut impl Write) -> Result<()> {
+        value.encode(writer)
+    }
+
+    pub fn decode(ctx: &mut DecodeContext<impl Read>) -> Result<String> {
+        Ok(String::decode_with(ctx)?.to_uppercase())
+    }
+}
+
+/// Doesn't round-trip, since lowercase letters come back uppercase.
+#[derive(Debug, PartialEq, Arbitrary, Decode, Encode)]
+struct Shout(#[protocol(with = "upper")] String);
+
+#[test]
+fn roundtrip() {
+    check_roundtrip::<Flags>();
+    check_roundtrip::<RoomInfo>();
+    check_roundtrip::<Packet>();
+    check_roundtrip::<Signed<Vec<Var<u32>>>>();
+    check_roundtrip::<Wide>();
+}
+
+#[test]
+fn truncated() {
+    check_truncated::<Flags>();
+    check_truncated::<RoomInfo>();
+    check_truncated::<Packet>();
+    check_truncated::<Wide>();
+}
+
+#[test]
+fn skipped_fields() {
+    let mut runner = TestRunner::deterministic();
+    for _ in 0..32 {
+        let flags = any::<Flags>().new_tree(&mut runner).unwrap().current();
+        assert_eq!(flags.color, "red");
+
+        let info = any::<RoomInfo>().new_tree(&mut runner).unwrap().current();
+        assert_eq!(info.cached, None);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Shout")]
+fn lossy() {
+    check_roundtrip::<Shout>();
+}
@@ -0,0 +1,259 @@
+use common::*;
+use protocol::{AsyncDecode, AsyncEncode, Decode, DecodeContext, ErrorKind, Limit, Limits};
+use protocol_derive::{AsyncDecode, Decode, Encode};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+use tokio::io::AsyncWriteExt;
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+struct Message {
+    sender: String,
+    contents: String,
+}
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+struct Flags {
+    #[protocol(var)]
+    id: u32,
+    pinned: bool,
+    #[protocol(skip, default = "default_color")]
+    color: String,
+    muted: bool,
+    #[protocol(with = "upper")]
+    title: String,
+    scores: BTreeMap<char, i64>,
+    tags: HashSet<[u8; 2]>,
+}
+
+fn default_color() -> String {
+    "red".to_string()
+}
+
+mod upper {
+    use protocol::{AsyncDecode, Decode, DecodeContext, Encode, Result};
+    use std::io::{Read, Write};
+    use tokio::io::AsyncRead;
+
+    pub fn encode(value: &str, writer: &mut impl Write) -> Result<()> {
+        value.to_lowercase().encode(writer)
+    }
+
+    pub fn decode(ctx: &mut DecodeContext<impl Read>) -> Result<String> {
+        Ok(String::decode_with(ctx)?.to_uppercase())
+    }
+
+    pub async fn decode_async<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<String> {
+        Ok(String::decode_async_with(ctx).await?.to_uppercase())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+#[protocol(extensible)]
+struct RoomInfoV1 {
+    id: String,
+    pinned: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+#[protocol(extensible)]
+struct RoomInfoV2 {
+    id: String,
+    pinned: bool,
+    #[protocol(since = 2)]
+    long_about: String,
+    #[protocol(since = 2, var)]
+    capacity: u16,
+}
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+enum Packet {
+    Ping,
+    RequestRoomInfo(String),
+    #[protocol(tag = 8)]
+    Message(Message),
+    Signed(Signed<Box<Packet>>),
+    Batch {
+        packets: Vec<Packet>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, AsyncDecode, Decode, Encode)]
+struct Signed<T> {
+    payload: T,
+    signature: Option<(u64, Vec<u8>)>,
+}
+
+fn message() -> Message {
+    Message {
+        sender: "marceline".to_string(),
+        contents: "hello".to_string(),
+    }
+}
+
+fn flags() -> Flags {
+    Flags {
+        id: 300,
+        pinned: true,
+        color: "red".to_string(),
+        muted: false,
+        title: "LOBBY".to_string(),
+        scores: [('a', -1), ('b', i64::MAX)].into_iter().collect(),
+        tags: [[1, 2], [3, 4]].into_iter().collect(),
+    }
+}
+
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::Ping,
+        Packet::RequestRoomInfo("lobby".to_string()),
+        Packet::Message(message()),
+        Packet::Signed(Signed {
+            payload: Box::new(Packet::Batch {
+                packets: vec![Packet::Ping, Packet::Message(message())],
+            }),
+            signature: Some((42, vec![0xff; 3])),
+        }),
+    ]
+}
+
+/// Decodes `buf` asynchronously, checking that it agrees with [Decode].
+async fn test_decode<T>(buf: &[u8]) -> T
+where
+    T: AsyncDecode + Decode + Debug + PartialEq,
+{
+    let mut reader = buf;
+    let decoded = T::decode_async(&mut reader).await.unwrap();
+    assert!(reader.is_empty(), "Trailing bytes after decoding!");
+    assert_eq!(decoded, T::decode_from(buf));
+    decoded
+}
+
+#[tokio::test]
+async fn roundtrip() {
+    assert_eq!(test_decode::<Message>(&encode(&message())).await, message());
+    assert_eq!(test_decode::<Flags>(&encode(&flags())).await, flags());
+
+    for packet in packets() {
+        assert_eq!(test_decode::<Packet>(&encode(&packet)).await, packet);
+    }
+}
+
+#[tokio::test]
+async fn extensible() {
+    let v1 = RoomInfoV1 {
+        id: "lobby".to_string(),
+        pinned: true,
+    };
+
+    let v2 = RoomInfoV2 {
+        id: "lobby".to_string(),
+        pinned: true,
+        long_about: "a room".to_string(),
+        capacity: 300,
+    };
+
+    assert_eq!(test_decode::<RoomInfoV1>(&encode(&v2)).await, v1);
+    assert_eq!(test_decode::<RoomInfoV2>(&encode(&v2)).await, v2);
+
+    let upgraded = test_decode::<RoomInfoV2>(&encode(&v1)).await;
+    assert_eq!(upgraded.long_about, "");
+    assert_eq!(upgraded.capacity, 0);
+}
+
+#[tokio::test]
+async fn stream() {
+    let (mut client, mut server) = tokio::io::duplex(64);
+
+    let writer = tokio::spawn(async move {
+        for packet in packets() {
+            // a byte at a time, so that every read has to wait for more
+            for byte in encode(&packet) {
+                client.write_all(&[byte]).await.unwrap();
+            }
+        }
+    });
+
+    for packet in packets() {
+        assert_eq!(Packet::decode_async(&mut server).await.unwrap(), packet);
+    }
+
+    writer.await.unwrap();
+    let err = Packet::decode_async(&mut server).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+}
+
+#[tokio::test]
+async fn encode_async() {
+    for packet in packets() {
+        let mut buf = Vec::new();
+        packet.encode_async(&mut buf).await.unwrap();
+        assert_eq!(buf, encode(&packet));
+    }
+
+    let mut buf = Vec::new();
+    "unsized".encode_async(&mut buf).await.unwrap();
+    assert_eq!(buf, encode(&"unsized"));
+}
+
+#[tokio::test]
+async fn errors() {
+    let mut buf = encode(&Packet::Message(message()));
+    let last = buf.len() - 1;
+    buf[last] = 0xff;
+    let err = Packet::decode_async(&mut buf.as_slice()).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidUtf8));
+    assert_eq!(err.to_string(), "Packet::Message.0.contents: invalid UTF-8");
+
+    let err = Packet::decode_async(&mut [4].as_slice()).await.unwrap_err();
+    assert_eq!(err.to_string(), "Packet: unknown discriminant 4");
+
+    let buf = encode(&Packet::Message(message()));
+    let err = Packet::decode_async(&mut &buf[..buf.len() - 1])
+        .await
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+
+    let err = RoomInfoV1::decode_async(&mut [0xff, 0xff, 0x7f].as_slice())
+        .await
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+
+    let err = HashSet::<u8>::decode_async(&mut [2, 5, 5].as_slice())
+        .await
+        .unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Malformed("duplicate key")));
+}
+
+#[tokio::test]
+async fn limits() {
+    let mut packet = Packet::Ping;
+    for _ in 0..4 {
+        packet = Packet::Signed(Signed {
+            payload: Box::new(packet),
+            signature: None,
+        });
+    }
+
+    let buf = encode(&packet);
+    let limits = Limits {
+        max_depth: 4,
+        ..Limits::default()
+    };
+
+    let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+    let err = Packet::decode_async_with(&mut ctx).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Depth)));
+
+    let limits = Limits {
+        max_bytes: buf.len() - 1,
+        ..Limits::default()
+    };
+
+    let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+    let err = Packet::decode_async_with(&mut ctx).await.unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+}
@@ -0,0 +1,91 @@
+use common::*;
+use protocol_derive::{Decode, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Counter {
+    #[protocol(var)]
+    count: u32,
+    fixed: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Cached {
+    name: String,
+    #[protocol(skip)]
+    name_len: usize,
+    #[protocol(skip, default = "default_color")]
+    color: String,
+}
+
+fn default_color() -> String {
+    "red".to_string()
+}
+
+mod upper {
+    use protocol::{Decode, DecodeContext, Encode, Result};
+    use std::io::{Read, Write};
+
+    pub fn encode(value: &str, writer: &mut impl Write) -> Result<()> {
+        value.to_lowercase().encode(writer)
+    }
+
+    pub fn decode(ctx: &mut DecodeContext<impl Read>) -> Result<String> {
+        Ok(String::decode_with(ctx)?.to_uppercase())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Shout(#[protocol(with = "upper")] String);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Event {
+    Scroll {
+        #[protocol(var)]
+        lines: u64,
+    },
+    Seen(#[protocol(skip)] bool, #[protocol(var)] u16),
+}
+
+#[test]
+fn var() {
+    let counter = Counter { count: 1, fixed: 1 };
+    assert_eq!(encode(&counter), [1, 1, 0, 0, 0]);
+    test_roundtrip(counter);
+    test_roundtrip(Counter {
+        count: u32::MAX,
+        fixed: u32::MAX,
+    });
+}
+
+#[test]
+fn skip() {
+    let cached = Cached {
+        name: "abc".to_string(),
+        name_len: 3,
+        color: "blue".to_string(),
+    };
+
+    let buf = encode(&cached);
+    assert_eq!(buf, [3, b'a', b'b', b'c']);
+
+    let decoded = Cached::decode_from(&buf);
+    assert_eq!(decoded.name, "abc");
+    assert_eq!(decoded.name_len, 0);
+    assert_eq!(decoded.color, "red");
+}
+
+#[test]
+fn with() {
+    let buf = encode(&Shout("Hello".to_string()));
+    assert_eq!(buf, [5, b'h', b'e', b'l', b'l', b'o']);
+    assert_eq!(Shout::decode_from(&buf), Shout("HELLO".to_string()));
+}
+
+#[test]
+fn variant_fields() {
+    test_roundtrip(Event::Scroll { lines: 300 });
+    assert_eq!(encode(&Event::Seen(true, 2)), [1, 2]);
+    assert_eq!(Event::decode_from(&[1, 2]), Event::Seen(false, 2));
+}
@@ -0,0 +1,136 @@
+use common::*;
+use protocol::{Decode, ErrorKind};
+use protocol_derive::{Decode, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Flags {
+    case_sensitive: bool,
+    name: String,
+    plural: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct ManyFlags(
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    bool,
+    u8,
+    bool,
+);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Unpacked {
+    packed: bool,
+    #[protocol(skip)]
+    skipped: bool,
+    #[protocol(with = "protocol_bool")]
+    standalone: bool,
+}
+
+mod protocol_bool {
+    use protocol::{Decode, DecodeContext, Encode, Result};
+    use std::io::{Read, Write};
+
+    pub fn encode(value: &bool, writer: &mut impl Write) -> Result<()> {
+        value.encode(writer)
+    }
+
+    pub fn decode(ctx: &mut DecodeContext<impl Read>) -> Result<bool> {
+        bool::decode_with(ctx)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Toggle {
+    Off,
+    On { loud: bool, level: u8, bright: bool },
+}
+
+#[test]
+fn packed_first() {
+    let flags = Flags {
+        case_sensitive: true,
+        name: "a".to_string(),
+        plural: true,
+    };
+
+    assert_eq!(encode(&flags), [0b11, 1, b'a']);
+    test_roundtrip(flags);
+}
+
+#[test]
+fn declaration_order() {
+    let flags = Flags {
+        case_sensitive: false,
+        name: "".to_string(),
+        plural: true,
+    };
+
+    assert_eq!(encode(&flags), [0b10, 0]);
+    test_roundtrip(flags);
+}
+
+#[test]
+fn multiple_bytes() {
+    let flags = ManyFlags(
+        true, false, false, false, false, false, false, true, true, 7, false,
+    );
+
+    assert_eq!(encode(&flags), [0b1000_0001, 0b0000_0001, 7]);
+    test_roundtrip(flags);
+
+    for index in 0..10 {
+        let mut bits = [false; 10];
+        bits[index] = true;
+        test_roundtrip(ManyFlags(
+            bits[0], bits[1], bits[2], bits[3], bits[4], bits[5], bits[6], bits[7], bits[8], 0,
+            bits[9],
+        ));
+    }
+}
+
+#[test]
+fn unused_bits() {
+    let err = Flags::decode(&mut [0b111, 0].as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Flags: unused bits set in bitset");
+
+    let err = ManyFlags::decode(&mut [0, 0b100, 0].as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Malformed(_)));
+}
+
+#[test]
+fn attributes_unpacked() {
+    let unpacked = Unpacked {
+        packed: true,
+        skipped: true,
+        standalone: true,
+    };
+
+    assert_eq!(encode(&unpacked), [0b1, 1]);
+
+    let decoded = Unpacked::decode_from(&encode(&unpacked));
+    assert!(decoded.packed);
+    assert!(!decoded.skipped);
+    assert!(decoded.standalone);
+}
+
+#[test]
+fn variant() {
+    let on = Toggle::On {
+        loud: false,
+        level: 11,
+        bright: true,
+    };
+
+    assert_eq!(encode(&on), [1, 0b10, 11]);
+    test_roundtrip(on);
+    test_roundtrip(Toggle::Off);
+}
@@ -0,0 +1,166 @@
+use common::*;
+use protocol::{DecodeRef, ErrorKind};
+use protocol_derive::{Decode, DecodeRef, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Message {
+    sender: String,
+    contents: String,
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef, Encode)]
+struct MessageRef<'a> {
+    sender: &'a str,
+    contents: &'a str,
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef, Encode)]
+struct Flags<'a> {
+    #[protocol(var)]
+    id: u32,
+    pinned: bool,
+    #[protocol(skip)]
+    cached: Option<String>,
+    muted: bool,
+    tags: Vec<&'a str>,
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef, Encode)]
+struct Signed<'a, T> {
+    signature: &'a [u8],
+    payload: T,
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef, Encode)]
+enum PacketRef<'a> {
+    Ping,
+    RequestRoomInfo(&'a str),
+    #[protocol(tag = 8)]
+    Message(MessageRef<'a>),
+    Signed(Signed<'a, MessageRef<'a>>),
+}
+
+mod borrowed_upper {
+    use protocol::{DecodeContext, DecodeRef, Result};
+
+    pub fn decode_ref(ctx: &mut DecodeContext<&[u8]>) -> Result<String> {
+        Ok(<&str>::decode_ref_with(ctx)?.to_uppercase())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef)]
+struct Shouted {
+    #[protocol(with = "borrowed_upper")]
+    text: String,
+}
+
+fn decode_ref<'de, T: DecodeRef<'de>>(mut buf: &'de [u8]) -> T {
+    let decoded = T::decode_ref(&mut buf).unwrap();
+    assert!(buf.is_empty(), "Trailing bytes after decoding!");
+    decoded
+}
+
+#[test]
+fn matches_owned() {
+    let owned = Message {
+        sender: "marceline".to_string(),
+        contents: "hello".to_string(),
+    };
+
+    let buf = encode(&owned);
+    let borrowed: MessageRef = decode_ref(&buf);
+    assert_eq!(borrowed.sender, owned.sender);
+    assert_eq!(borrowed.contents, owned.contents);
+    assert_eq!(encode(&borrowed), buf);
+    assert!(buf.as_ptr_range().contains(&borrowed.contents.as_ptr()));
+}
+
+#[test]
+fn attributes() {
+    let original = Flags {
+        id: 300,
+        pinned: true,
+        cached: None,
+        muted: false,
+        tags: vec!["a", "b"],
+    };
+
+    let buf = encode(&original);
+    assert_eq!(buf[..3], [0b01, 0xac, 0x02]);
+    assert_eq!(decode_ref::<Flags>(&buf), original);
+}
+
+#[test]
+fn generics() {
+    let original = Signed {
+        signature: &[1, 2, 3],
+        payload: 42u32,
+    };
+
+    assert_eq!(decode_ref::<Signed<u32>>(&encode(&original)), original);
+}
+
+#[test]
+fn enums() {
+    let message = MessageRef {
+        sender: "a",
+        contents: "b",
+    };
+
+    let buf = encode(&PacketRef::Message(message));
+    assert_eq!(buf[0], 8);
+    assert_eq!(
+        decode_ref::<PacketRef>(&buf),
+        PacketRef::Message(MessageRef {
+            sender: "a",
+            contents: "b",
+        })
+    );
+
+    for packet in [
+        PacketRef::Ping,
+        PacketRef::RequestRoomInfo("lobby"),
+        PacketRef::Signed(Signed {
+            signature: &[0xff],
+            payload: MessageRef {
+                sender: "c",
+                contents: "d",
+            },
+        }),
+    ] {
+        assert_eq!(decode_ref::<PacketRef>(&encode(&packet)), packet);
+    }
+}
+
+#[test]
+fn with() {
+    let buf = encode(&"Hello");
+    assert_eq!(
+        decode_ref::<Shouted>(&buf),
+        Shouted {
+            text: "HELLO".to_string()
+        }
+    );
+}
+
+#[test]
+fn errors() {
+    let mut buf = encode(&PacketRef::Message(MessageRef {
+        sender: "a",
+        contents: "b",
+    }));
+
+    let last = buf.len() - 1;
+    buf[last] = 0xff;
+    let err = PacketRef::decode_ref(&mut buf.as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidUtf8));
+    assert_eq!(
+        err.to_string(),
+        "PacketRef::Message.0.contents: invalid UTF-8"
+    );
+
+    let err = PacketRef::decode_ref(&mut [4].as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "PacketRef: unknown discriminant 4");
+}
@@ -0,0 +1,30 @@
+#![allow(dead_code)]
+
+use protocol::{Decode, Encode};
+use std::fmt::Debug;
+
+pub fn test_roundtrip<T: Debug + Eq + Encode + Decode>(original: T) {
+    let buf = encode(&original);
+    let mut reader = buf.as_slice();
+    let decoded = T::decode(&mut reader).unwrap();
+    assert_eq!(original, decoded, "Round-trip encoded values do not match!");
+    assert!(reader.is_empty(), "Trailing bytes after decoding!");
+}
+
+/// Encodes `value`, checking `Encode::encoded_len` along the way.
+pub fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf).unwrap();
+    assert_eq!(value.encoded_len(), buf.len(), "Wrong encoded length!");
+    buf
+}
+
+pub trait DecodeFrom: Decode {
+    fn decode_from(mut buf: &[u8]) -> Self {
+        let decoded = Self::decode(&mut buf).unwrap();
+        assert!(buf.is_empty(), "Trailing bytes after decoding!");
+        decoded
+    }
+}
+
+impl<T: Decode> DecodeFrom for T {}
@@ -0,0 +1,66 @@
+use common::*;
+use protocol::*;
+use protocol_derive::{Decode, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Shapes {
+    Unit,
+    Newtype(String),
+    Tuple(u8, u32, String),
+    Struct { x: i16, y: i16 },
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Tagged {
+    Zero,
+    #[protocol(tag = 10)]
+    Ten,
+    Eleven,
+    #[protocol(tag = 300)]
+    ThreeHundred(u8),
+}
+
+#[test]
+fn unit_variant() {
+    test_roundtrip(Shapes::Unit);
+}
+
+#[test]
+fn newtype_variant() {
+    test_roundtrip(Shapes::Newtype("hello".to_string()));
+}
+
+#[test]
+fn tuple_variant() {
+    test_roundtrip(Shapes::Tuple(1, 2, "three".to_string()));
+}
+
+#[test]
+fn struct_variant() {
+    test_roundtrip(Shapes::Struct { x: -4, y: 5 });
+}
+
+#[test]
+fn implicit_tags() {
+    assert_eq!(encode(&Shapes::Unit), [0]);
+    assert_eq!(encode(&Shapes::Struct { x: 0, y: 0 }), [3, 0, 0, 0, 0]);
+}
+
+#[test]
+fn explicit_tags() {
+    assert_eq!(encode(&Tagged::Zero), [0]);
+    assert_eq!(encode(&Tagged::Ten), [10]);
+    assert_eq!(encode(&Tagged::Eleven), [11]);
+    assert_eq!(encode(&Tagged::ThreeHundred(7)), [0xac, 0x02, 7]);
+    test_roundtrip(Tagged::ThreeHundred(7));
+}
+
+#[test]
+fn unknown_tag() {
+    let mut reader: &[u8] = &[1];
+    let err = Tagged::decode(&mut reader).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::UnknownDiscriminant(1)));
+    assert_eq!(err.to_string(), "Tagged: unknown discriminant 1");
+}
@@ -0,0 +1,126 @@
+use common::*;
+use protocol::{Decode, ErrorKind};
+use protocol_derive::{Decode, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct RoomInfo {
+    id: String,
+    title: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct RoomList(Vec<RoomInfo>);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Packet {
+    Ping,
+    RoomInfo(RoomInfo),
+    RoomList { rooms: RoomList },
+}
+
+mod io_codec {
+    use protocol::DecodeContext;
+    use std::io::{Error, Read, Result, Write};
+
+    pub fn encode(_value: &u8, _writer: &mut impl Write) -> Result<()> {
+        Err(Error::other("encoding is broken"))
+    }
+
+    pub fn decode(_ctx: &mut DecodeContext<impl Read>) -> Result<u8> {
+        Err(Error::other("decoding is broken"))
+    }
+}
+
+#[derive(Debug, Decode, Encode)]
+struct Broken {
+    #[protocol(with = "io_codec")]
+    byte: u8,
+}
+
+fn room(title: &str) -> RoomInfo {
+    RoomInfo {
+        id: "id".to_string(),
+        title: title.to_string(),
+    }
+}
+
+/// Replaces the bytes of every `needle` in `buf` with invalid UTF-8.
+fn corrupt(buf: &mut [u8], needle: &str) {
+    let needle = needle.as_bytes();
+    let start = buf
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .unwrap();
+    buf[start] = 0xff;
+}
+
+#[test]
+fn field_path() {
+    let mut buf = encode(&room("title"));
+    corrupt(&mut buf, "title");
+
+    let err = RoomInfo::decode(&mut buf.as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidUtf8));
+    assert_eq!(err.path().unwrap(), "RoomInfo.title");
+    assert_eq!(err.to_string(), "RoomInfo.title: invalid UTF-8");
+}
+
+#[test]
+fn nested_path() {
+    let mut buf = encode(&Packet::RoomInfo(room("title")));
+    corrupt(&mut buf, "title");
+    let err = Packet::decode(&mut buf.as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Packet::RoomInfo.0.title: invalid UTF-8");
+
+    let rooms = RoomList(vec![room("a"), room("title")]);
+    let mut buf = encode(&Packet::RoomList { rooms });
+    corrupt(&mut buf, "title");
+    let err = Packet::decode(&mut buf.as_slice()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Packet::RoomList.rooms.0.title: invalid UTF-8"
+    );
+}
+
+#[test]
+fn truncated() {
+    let buf = encode(&room("title"));
+    let err = RoomInfo::decode(&mut &buf[..buf.len() - 1]).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+    assert_eq!(err.to_string(), "RoomInfo.title: unexpected end of input");
+
+    let err = Packet::decode(&mut [].as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Packet: unexpected end of input");
+}
+
+#[test]
+fn unknown_discriminant() {
+    let err = Packet::decode(&mut [7].as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::UnknownDiscriminant(7)));
+    assert_eq!(err.to_string(), "Packet: unknown discriminant 7");
+}
+
+#[test]
+fn with_io_error() {
+    let mut buf = Vec::new();
+    let err = protocol::Encode::encode(&Broken { byte: 0 }, &mut buf).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Io(_)));
+    assert_eq!(err.to_string(), "Broken.byte: encoding is broken");
+
+    let err = Broken::decode(&mut [0].as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Broken.byte: decoding is broken");
+}
+
+#[test]
+fn into_io_error() {
+    let err = Packet::decode(&mut [7].as_slice()).unwrap_err();
+    let err = std::io::Error::from(err);
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Packet: unknown discriminant 7");
+
+    let err = Packet::decode(&mut [].as_slice()).unwrap_err();
+    let err = std::io::Error::from(err);
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,137 @@
+use common::*;
+use protocol::{Decode, DecodeRef, ErrorKind, Limit};
+use protocol_derive::{Decode, DecodeRef, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+#[protocol(extensible)]
+struct RoomInfoV1 {
+    id: String,
+    pinned: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+#[protocol(extensible)]
+struct RoomInfoV2 {
+    id: String,
+    pinned: bool,
+    #[protocol(since = 2)]
+    long_about: String,
+    #[protocol(since = 2)]
+    archived: bool,
+    #[protocol(skip)]
+    cached: u8,
+    #[protocol(since = 3, var, default = "default_capacity")]
+    capacity: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, DecodeRef, Encode)]
+#[protocol(extensible)]
+struct RoomInfoRef<'a> {
+    id: &'a str,
+    pinned: bool,
+    #[protocol(since = 2)]
+    long_about: &'a str,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+#[protocol(extensible)]
+struct Empty;
+
+fn default_capacity() -> u32 {
+    16
+}
+
+fn v1() -> RoomInfoV1 {
+    RoomInfoV1 {
+        id: "lobby".to_string(),
+        pinned: true,
+    }
+}
+
+fn v2() -> RoomInfoV2 {
+    RoomInfoV2 {
+        id: "lobby".to_string(),
+        pinned: true,
+        long_about: "a room".to_string(),
+        archived: true,
+        cached: 0,
+        capacity: 300,
+    }
+}
+
+#[test]
+fn roundtrip() {
+    test_roundtrip(v1());
+    test_roundtrip(v2());
+    test_roundtrip(Empty);
+    assert_eq!(encode(&v1()), [7, 0b1, 5, b'l', b'o', b'b', b'b', b'y']);
+    assert_eq!(encode(&Empty), [0]);
+}
+
+#[test]
+fn missing_fields() {
+    let decoded = RoomInfoV2::decode_from(&encode(&v1()));
+    assert_eq!(
+        decoded,
+        RoomInfoV2 {
+            id: "lobby".to_string(),
+            pinned: true,
+            long_about: String::new(),
+            archived: false,
+            cached: 0,
+            capacity: 16,
+        }
+    );
+}
+
+#[test]
+fn unknown_fields() {
+    assert_eq!(RoomInfoV1::decode_from(&encode(&v2())), v1());
+
+    let rooms = vec![v2(), v2()];
+    let decoded = Vec::<RoomInfoV1>::decode_from(&encode(&rooms));
+    assert_eq!(decoded, [v1(), v1()]);
+}
+
+#[test]
+fn borrowed() {
+    let buf = encode(&v2());
+    let mut reader = buf.as_slice();
+    let decoded = RoomInfoRef::decode_ref(&mut reader).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(
+        decoded,
+        RoomInfoRef {
+            id: "lobby",
+            pinned: true,
+            long_about: "a room",
+        }
+    );
+
+    let buf = encode(&v1());
+    let decoded = RoomInfoRef::decode_ref(&mut buf.as_slice()).unwrap();
+    assert_eq!(decoded.long_about, "");
+}
+
+#[test]
+fn truncated() {
+    let buf = encode(&v2());
+    let err = RoomInfoV1::decode(&mut &buf[..buf.len() - 1]).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+    assert_eq!(err.to_string(), "RoomInfoV1: unexpected end of input");
+
+    // the body ends in the middle of the id
+    let mut buf = encode(&v1());
+    buf[0] = 3;
+    let err = RoomInfoV1::decode(&mut buf.as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+    assert_eq!(err.to_string(), "RoomInfoV1.id: unexpected end of input");
+}
+
+#[test]
+fn byte_limit() {
+    let err = RoomInfoV1::decode(&mut [0xff, 0xff, 0x7f].as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+}
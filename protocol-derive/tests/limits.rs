@@ -0,0 +1,40 @@
+use common::*;
+use protocol::{Decode, DecodeContext, ErrorKind, Limit, Limits};
+use protocol_derive::{Decode, Encode};
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum List {
+    Nil,
+    Cons(u8, Box<List>),
+}
+
+impl List {
+    fn with_len(len: usize) -> Self {
+        (0..len).fold(List::Nil, |tail, _| List::Cons(0, Box::new(tail)))
+    }
+}
+
+#[test]
+fn recursion() {
+    test_roundtrip(List::with_len(5));
+
+    // two levels per cons cell, from the list itself and its box
+    let buf = encode(&List::with_len(Limits::NETWORK.max_depth / 2));
+    let err = List::decode(&mut buf.as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Depth)));
+}
+
+#[test]
+fn custom_depth() {
+    let limits = Limits {
+        max_depth: 1000,
+        ..Limits::NETWORK
+    };
+
+    let list = List::with_len(100);
+    let buf = encode(&list);
+    let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+    assert_eq!(List::decode_with(&mut ctx).unwrap(), list);
+}
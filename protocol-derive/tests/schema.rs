@@ -0,0 +1,174 @@
+// these types are only ever described, never constructed
+#![allow(dead_code)]
+
+use protocol::{Describe, FieldDef, Schema, TypeKind};
+use protocol_derive::Describe;
+
+/// Information about a room.
+#[derive(Describe)]
+#[protocol(extensible)]
+struct RoomInfo {
+    /// Unique ID of the room.
+    id: String,
+    pinned: bool,
+    #[protocol(var)]
+    members: u32,
+    #[protocol(skip)]
+    _cached: Vec<u8>,
+    #[protocol(with = "upper")]
+    title: String,
+    #[protocol(since = 2)]
+    archived: bool,
+}
+
+mod upper {}
+
+#[derive(Describe)]
+enum Packet<'a> {
+    Ping,
+    /// Asks for a room's info by its ID.
+    RequestRoomInfo(&'a str),
+    #[protocol(tag = 8)]
+    RoomInfo(RoomInfo),
+    RoomList {
+        rooms: Vec<RoomInfo>,
+        owner: Option<Box<Packet<'a>>>,
+    },
+}
+
+#[derive(Describe)]
+struct Signed<T>(T, [u8; 4]);
+
+fn field(name: &str, ty: &str) -> FieldDef {
+    FieldDef {
+        name: name.to_string(),
+        doc: None,
+        ty: ty.to_string(),
+        bit: None,
+        since: None,
+        with: None,
+    }
+}
+
+#[test]
+fn type_names() {
+    assert_eq!(RoomInfo::type_name(), "RoomInfo");
+    assert_eq!(Signed::<u32>::type_name(), "Signed<u32>");
+    assert_eq!(<Vec<Option<String>>>::type_name(), "list<option<string>>");
+    assert_eq!(<&[u8]>::type_name(), "list<u8>");
+    assert_eq!(
+        <(protocol::Var<i64>, char)>::type_name(),
+        "tuple<var<i64>, char>"
+    );
+}
+
+#[test]
+fn fields() {
+    let schema = Schema::of::<RoomInfo>();
+    assert_eq!(schema.types.len(), 1);
+
+    let def = &schema.types[0];
+    assert_eq!(def.name, "RoomInfo");
+    assert_eq!(def.doc.as_deref(), Some("Information about a room."));
+
+    let TypeKind::Struct {
+        extensible,
+        ref fields,
+    } = def.kind
+    else {
+        panic!("expected a struct");
+    };
+
+    assert!(extensible);
+    assert_eq!(
+        *fields,
+        [
+            FieldDef {
+                doc: Some("Unique ID of the room.".to_string()),
+                ..field("id", "string")
+            },
+            FieldDef {
+                bit: Some(0),
+                ..field("pinned", "bool")
+            },
+            field("members", "var<u32>"),
+            FieldDef {
+                with: Some("upper".to_string()),
+                ..field("title", "String")
+            },
+            FieldDef {
+                since: Some(2),
+                ..field("archived", "bool")
+            },
+        ]
+    );
+}
+
+#[test]
+fn catalogue() {
+    let schema = Schema::of::<Packet>();
+    let names: Vec<_> = schema.types.iter().map(|def| def.name.as_str()).collect();
+    assert_eq!(names, ["Packet", "RoomInfo"]);
+
+    let TypeKind::Enum { ref variants } = schema.types[0].kind else {
+        panic!("expected an enum");
+    };
+
+    let tags: Vec<_> = variants.iter().map(|v| (v.name.as_str(), v.tag)).collect();
+    assert_eq!(
+        tags,
+        [
+            ("Ping", 0),
+            ("RequestRoomInfo", 1),
+            ("RoomInfo", 8),
+            ("RoomList", 9)
+        ]
+    );
+
+    assert_eq!(variants[1].fields, [field("0", "string")]);
+    assert_eq!(
+        variants[3].fields,
+        [
+            field("rooms", "list<RoomInfo>"),
+            field("owner", "option<Packet>")
+        ]
+    );
+}
+
+#[test]
+fn json() {
+    let json = Schema::of::<Signed<bool>>().to_json();
+    assert_eq!(
+        json,
+        r#"{
+  "types": [
+    {
+      "name": "Signed<bool>",
+      "kind": "struct",
+      "extensible": false,
+      "fields": [
+        {
+          "name": "0",
+          "type": "bool"
+        },
+        {
+          "name": "1",
+          "type": "array<u8, 4>"
+        }
+      ]
+    }
+  ]
+}
+"#
+    );
+}
+
+#[test]
+fn markdown() {
+    let markdown = Schema::of::<Packet>().to_markdown();
+    assert!(markdown.starts_with("# Wire format\n\n"));
+    assert!(markdown.contains(Schema::PRELUDE));
+    assert!(markdown.contains("| 8 | `RoomInfo` | 0: `RoomInfo` |"));
+    assert!(markdown.contains("| `pinned` | `bool` | bit 0 |"));
+    assert!(markdown.contains("| `title` | `String` | custom encoding by `upper` |"));
+}
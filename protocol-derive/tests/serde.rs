@@ -0,0 +1,207 @@
+use common::*;
+use protocol::serde::{from_reader, to_vec, Deserializer};
+use protocol::{BitSet, ErrorKind, Limit, Limits, Var};
+use protocol_derive::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode, Deserialize, Serialize)]
+struct Message {
+    sender: String,
+    contents: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode, Deserialize, Serialize)]
+struct Numbers {
+    id: Var<u32>,
+    offset: Var<i64>,
+    fixed: u32,
+    byte: i8,
+    scores: BTreeMap<char, i64>,
+    pair: (u16, Option<String>),
+    mask: BitSet<2>,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode, Deserialize, Serialize)]
+enum Packet {
+    Ping,
+    RequestRoomInfo(String),
+    Message(Message),
+    Signed(Box<Packet>, [u8; 4]),
+    Batch {
+        packets: Vec<Packet>,
+        sender: Option<String>,
+    },
+}
+
+fn message() -> Message {
+    Message {
+        sender: "marceline".to_string(),
+        contents: "hello".to_string(),
+    }
+}
+
+fn numbers() -> Numbers {
+    let mut mask = BitSet::new();
+    mask.set(3, true);
+    mask.set(9, true);
+
+    Numbers {
+        id: Var(300),
+        offset: Var(-70000),
+        fixed: 300,
+        byte: -1,
+        scores: [('a', -1), ('b', i64::MAX)].into_iter().collect(),
+        pair: (7, Some("seven".to_string())),
+        mask,
+    }
+}
+
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::Ping,
+        Packet::RequestRoomInfo("lobby".to_string()),
+        Packet::Message(message()),
+        Packet::Signed(
+            Box::new(Packet::Batch {
+                packets: vec![Packet::Ping, Packet::Message(message())],
+                sender: None,
+            }),
+            [1, 2, 3, 4],
+        ),
+    ]
+}
+
+#[test]
+fn same_bytes() {
+    assert_eq!(to_vec(&message()).unwrap(), encode(&message()));
+    assert_eq!(to_vec(&numbers()).unwrap(), encode(&numbers()));
+
+    for packet in packets() {
+        assert_eq!(to_vec(&packet).unwrap(), encode(&packet));
+    }
+}
+
+#[test]
+fn roundtrip() {
+    let buf = encode(&numbers());
+    assert_eq!(
+        from_reader::<Numbers>(&mut buf.as_slice()).unwrap(),
+        numbers()
+    );
+
+    for packet in packets() {
+        let buf = encode(&packet);
+        let mut reader = buf.as_slice();
+        assert_eq!(from_reader::<Packet>(&mut reader).unwrap(), packet);
+        assert!(reader.is_empty(), "Trailing bytes after decoding!");
+    }
+}
+
+#[test]
+fn bools() {
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Flags {
+        pinned: bool,
+        muted: bool,
+        name: String,
+    }
+
+    let flags = Flags {
+        pinned: true,
+        muted: false,
+        name: "a".to_string(),
+    };
+
+    // unlike with Encode, each bool takes a whole byte
+    let buf = to_vec(&flags).unwrap();
+    assert_eq!(buf, [1, 0, 1, b'a']);
+    assert_eq!(from_reader::<Flags>(&mut buf.as_slice()).unwrap(), flags);
+}
+
+#[test]
+fn json() {
+    let json = serde_json::to_string(&numbers()).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":300,"offset":-70000,"fixed":300,"byte":-1,"scores":{"a":-1,"b":9223372036854775807},"pair":[7,"seven"],"mask":[8,2]}"#
+    );
+
+    assert_eq!(serde_json::from_str::<Numbers>(&json).unwrap(), numbers());
+}
+
+#[test]
+fn errors() {
+    let mut buf = encode(&Packet::Message(message()));
+    let last = buf.len() - 1;
+    buf[last] = 0xff;
+    let err = from_reader::<Packet>(&mut buf.as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidUtf8));
+    assert_eq!(err.to_string(), "Packet.Message.contents: invalid UTF-8");
+
+    let err = from_reader::<Packet>(&mut [5].as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Packet: unknown discriminant 5");
+
+    let buf = encode(&Packet::Message(message()));
+    let err = from_reader::<Packet>(&mut &buf[..buf.len() - 1]).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Truncated));
+
+    let err = from_reader::<serde_json::Value>(&mut [0].as_slice()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Custom(_)));
+
+    let err = to_vec(&Evens).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Custom(_)));
+}
+
+/// A sequence that doesn't know its length until it's been serialized, so
+/// that it can't be prefixed with it.
+struct Evens;
+
+impl Serialize for Evens {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq((0..4).filter(|n| n % 2 == 0))
+    }
+}
+
+#[test]
+fn limits() {
+    let mut packet = Packet::Ping;
+    for _ in 0..4 {
+        packet = Packet::Signed(Box::new(packet), [0; 4]);
+    }
+
+    let buf = encode(&packet);
+    let limits = Limits {
+        max_depth: 4,
+        ..Limits::default()
+    };
+
+    let mut de = Deserializer::new(buf.as_slice(), limits);
+    let err = Packet::deserialize(&mut de).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Depth)));
+
+    let limits = Limits {
+        max_bytes: buf.len() - 1,
+        ..Limits::default()
+    };
+
+    let mut de = Deserializer::new(buf.as_slice(), limits);
+    let err = Packet::deserialize(&mut de).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+
+    let limits = Limits {
+        max_string_len: 4,
+        ..Limits::default()
+    };
+
+    let buf = encode(&message());
+    let mut de = Deserializer::new(buf.as_slice(), limits);
+    let err = Message::deserialize(&mut de).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::LimitExceeded(Limit::StringLength)
+    ));
+    assert_eq!(err.path().as_deref(), Some("Message.sender"));
+}
@@ -0,0 +1,103 @@
+use common::*;
+use protocol_derive::{Decode, Encode};
+use std::fmt::Debug;
+
+mod common;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Named {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct RoomId(String);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Pair(u8, i64);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Marker;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Signed<T> {
+    signature: Vec<u8>,
+    payload: T,
+}
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Bounded<T: Debug, U>(T, Vec<U>)
+where
+    U: Eq;
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+struct Cached<T> {
+    id: u32,
+    #[protocol(skip)]
+    cache: T,
+}
+
+/// Neither `Encode` nor `Decode`, only `Default`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Unencodable(u8);
+
+#[derive(Debug, PartialEq, Eq, Decode, Encode)]
+enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+#[test]
+fn named() {
+    test_roundtrip(Named {
+        id: 42,
+        name: "named".to_string(),
+    });
+}
+
+#[test]
+fn newtype() {
+    test_roundtrip(RoomId("lobby".to_string()));
+    assert_eq!(encode(&RoomId("a".to_string())), [1, b'a']);
+}
+
+#[test]
+fn tuple() {
+    test_roundtrip(Pair(1, -1));
+}
+
+#[test]
+fn unit() {
+    test_roundtrip(Marker);
+    assert!(encode(&Marker).is_empty());
+}
+
+#[test]
+fn generic() {
+    test_roundtrip(Signed {
+        signature: vec![1, 2, 3],
+        payload: RoomId("lobby".to_string()),
+    });
+}
+
+#[test]
+fn generic_bounds() {
+    test_roundtrip(Bounded(5u8, vec!["where".to_string()]));
+}
+
+#[test]
+fn generic_skipped() {
+    let cached = Cached {
+        id: 7,
+        cache: Unencodable::default(),
+    };
+
+    assert_eq!(encode(&cached), encode(&7u32));
+    test_roundtrip(cached);
+}
+
+#[test]
+fn generic_enum() {
+    test_roundtrip(Either::<u8, String>::Left(1));
+    test_roundtrip(Either::<u8, String>::Right("right".to_string()));
+}
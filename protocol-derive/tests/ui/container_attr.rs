@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+#[protocol(var)]
+struct Room {
+    id: String,
+}
+
+fn main() {}
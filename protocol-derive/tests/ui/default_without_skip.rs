@@ -0,0 +1,9 @@
+use protocol_derive::Decode;
+
+#[derive(Decode)]
+struct Counter {
+    #[protocol(default = "u32::max_value")]
+    count: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+struct Counter {
+    #[protocol(var, var)]
+    count: u32,
+}
+
+fn main() {}
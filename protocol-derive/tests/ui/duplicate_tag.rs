@@ -0,0 +1,11 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Packet {
+    Ping,
+    Pong,
+    #[protocol(tag = 1)]
+    Message(String),
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Never {}
+
+fn main() {}
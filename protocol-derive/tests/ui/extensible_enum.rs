@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+#[protocol(extensible)]
+enum Packet {
+    Ping,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Packet {
+    #[protocol(tag = 65535)]
+    Ping,
+    Pong,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use protocol_derive::Decode;
+
+#[derive(Decode)]
+#[protocol(extensible)]
+struct Room {
+    id: String,
+    #[protocol(since = "2")]
+    title: String,
+}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use protocol_derive::Decode;
+
+#[derive(Decode)]
+#[protocol(extensible)]
+struct Room {
+    id: String,
+    #[protocol(since = 3)]
+    title: String,
+    #[protocol(since = 2)]
+    about: String,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use protocol_derive::Decode;
+
+#[derive(Decode)]
+struct Room {
+    id: String,
+    #[protocol(since = 2)]
+    title: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+struct Counter {
+    #[protocol(skip, var)]
+    count: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Packet {
+    #[protocol(tag = "one")]
+    Ping,
+}
+
+fn main() {}
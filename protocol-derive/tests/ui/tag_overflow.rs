@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Packet {
+    #[protocol(tag = 65536)]
+    Ping,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
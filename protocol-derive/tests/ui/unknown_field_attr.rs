@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+struct Room {
+    #[protocol(compressed)]
+    id: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+enum Packet {
+    #[protocol(var)]
+    Ping,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use protocol_derive::Encode;
+
+#[derive(Encode)]
+struct Flags {
+    #[protocol(var)]
+    enabled: bool,
+}
+
+fn main() {}
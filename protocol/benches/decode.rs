@@ -0,0 +1,69 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use protocol::{Decode, DecodeRef, Encode};
+use protocol_derive::{Decode, DecodeRef, Encode};
+
+#[derive(Decode, Encode)]
+struct Message {
+    sender: String,
+    contents: String,
+}
+
+// only ever decoded
+#[allow(dead_code)]
+#[derive(DecodeRef)]
+struct MessageRef<'a> {
+    sender: &'a str,
+    contents: &'a str,
+}
+
+#[derive(Decode, Encode)]
+struct RoomList {
+    room_ids: Vec<String>,
+}
+
+// only ever decoded
+#[allow(dead_code)]
+#[derive(DecodeRef)]
+struct RoomListRef<'a> {
+    room_ids: Vec<&'a str>,
+}
+
+fn encode(value: &impl Encode) -> Vec<u8> {
+    let mut buf = Vec::new();
+    value.encode(&mut buf).unwrap();
+    buf
+}
+
+fn message(c: &mut Criterion) {
+    let buf = encode(&Message {
+        sender: "marceline".to_string(),
+        contents: "the quick brown fox jumps over the lazy dog ".repeat(8),
+    });
+
+    let mut group = c.benchmark_group("message");
+    group.bench_function("owned", |b| {
+        b.iter(|| Message::decode(&mut black_box(buf.as_slice())).unwrap())
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| MessageRef::decode_ref(&mut black_box(buf.as_slice())).unwrap())
+    });
+    group.finish();
+}
+
+fn room_list(c: &mut Criterion) {
+    let buf = encode(&RoomList {
+        room_ids: (0..256).map(|i| format!("room_{}", i)).collect(),
+    });
+
+    let mut group = c.benchmark_group("room_list");
+    group.bench_function("owned", |b| {
+        b.iter(|| RoomList::decode(&mut black_box(buf.as_slice())).unwrap())
+    });
+    group.bench_function("borrowed", |b| {
+        b.iter(|| RoomListRef::decode_ref(&mut black_box(buf.as_slice())).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, message, room_list);
+criterion_main!(benches);
@@ -0,0 +1,310 @@
+use crate::{check_unique, BitSet, DecodeContext, Encode, ErrorKind, Limits, Result, Unique, Var};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::hash::{BuildHasher, Hash};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// Encodes a value to an [AsyncWrite].
+///
+/// Encoding never has to wait on anything but the writer, so every [Encode]
+/// type is encoded into memory first and then written out all at once.
+pub trait AsyncEncode: Encode + Sync {
+    fn encode_async<W: AsyncWrite + Unpin + Send>(
+        &self,
+        writer: &mut W,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+impl<T: Encode + Sync + ?Sized> AsyncEncode for T {
+    async fn encode_async<W: AsyncWrite + Unpin + Send>(&self, writer: &mut W) -> Result<()> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode(&mut buf)?;
+        writer.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+/// Decodes a value from an [AsyncRead], the asynchronous counterpart of
+/// [crate::Decode].
+///
+/// This reads the same wire format within the same [Limits], and never reads
+/// past the end of the value, so whatever follows it on a stream is left
+/// for the next one.
+pub trait AsyncDecode: Sized + Send {
+    /// Decodes a value within the [Limits] carried by `ctx`.
+    fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> impl Future<Output = Result<Self>> + Send;
+
+    /// Decodes a value with the default [Limits] for network input.
+    fn decode_async<R: AsyncRead + Unpin + Send>(
+        reader: &mut R,
+    ) -> impl Future<Output = Result<Self>> + Send {
+        async move {
+            let mut ctx = DecodeContext::new(reader, Limits::default());
+            Self::decode_async_with(&mut ctx).await
+        }
+    }
+}
+
+macro_rules! impl_fixed (
+    ($($type: ident),+) => (
+        $(
+            impl AsyncDecode for $type {
+                async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+                    ctx: &mut DecodeContext<R>,
+                ) -> Result<Self> {
+                    let mut buf = [0u8; std::mem::size_of::<$type>()];
+                    ctx.read_exact_async(&mut buf).await?;
+                    Ok($type::from_le_bytes(buf))
+                }
+            }
+        )+
+    )
+);
+
+impl_fixed!(u8, i8, u16, u32, u64, u128, i16, i32, i64, i128, f32, f64);
+
+impl AsyncDecode for bool {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        Ok(u8::decode_async_with(ctx).await? != 0)
+    }
+}
+
+/// Varints are read a byte at a time until their last byte, or until there
+/// are too many for the type, and are then decoded exactly like [crate::Decode]
+/// decodes them.
+macro_rules! impl_var (
+    ($($type: ident),+) => (
+        $(
+            impl AsyncDecode for Var<$type> {
+                async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+                    ctx: &mut DecodeContext<R>,
+                ) -> Result<Self> {
+                    const MAX_LEN: usize = $type::BITS.div_ceil(7) as usize;
+
+                    let mut buf = [0u8; MAX_LEN];
+                    let mut len = 0;
+
+                    while len < MAX_LEN {
+                        ctx.read_exact_async(&mut buf[len..len + 1]).await?;
+                        len += 1;
+
+                        if buf[len - 1] & 0x80 == 0 {
+                            break;
+                        }
+                    }
+
+                    let mut bytes = DecodeContext::new(&buf[..len], Limits::UNLIMITED);
+                    <Self as crate::Decode>::decode_with(&mut bytes)
+                }
+            }
+        )+
+    )
+);
+
+impl_var!(u16, u32, u64, u128, i16, i32, i64, i128);
+
+impl AsyncDecode for () {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        _ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl AsyncDecode for char {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        let code = Var::<u32>::decode_async_with(ctx).await?.0;
+        char::from_u32(code).ok_or_else(|| ErrorKind::Malformed("invalid char").into())
+    }
+}
+
+impl AsyncDecode for String {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        let len = Var::<u32>::decode_async_with(ctx).await?.0 as usize;
+        ctx.check_string_len(len)?;
+        let mut buf = vec![0u8; len];
+        ctx.read_exact_async(&mut buf).await?;
+        String::from_utf8(buf).map_err(|_| ErrorKind::InvalidUtf8.into())
+    }
+}
+
+impl<const N: usize> AsyncDecode for BitSet<N> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        let mut bits = Self::new();
+        ctx.read_exact_async(&mut bits.0).await?;
+        Ok(bits)
+    }
+}
+
+/// Decodes a `T` one nesting level deeper.
+async fn decode_nested<T, R>(ctx: &mut DecodeContext<R>) -> Result<T>
+where
+    T: AsyncDecode,
+    R: AsyncRead + Unpin + Send,
+{
+    ctx.enter()?;
+    let result = T::decode_async_with(ctx).await;
+    ctx.leave();
+    result
+}
+
+impl<T: AsyncDecode> AsyncDecode for Box<T> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        decode_nested(ctx).await.map(Box::new)
+    }
+}
+
+impl<T: AsyncDecode> AsyncDecode for Option<T> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        if bool::decode_async_with(ctx).await? {
+            decode_nested(ctx).await.map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! impl_tuple (
+    ($($name: ident),+) => (
+        impl<$($name: AsyncDecode),+> AsyncDecode for ($($name,)+) {
+            async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+                ctx: &mut DecodeContext<R>,
+            ) -> Result<Self> {
+                Ok(($($name::decode_async_with(ctx).await?,)+))
+            }
+        }
+    )
+);
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// Decodes `len` items one nesting level deeper, adding them to `items`.
+async fn decode_items<T, C, R>(ctx: &mut DecodeContext<R>, len: usize, mut items: C) -> Result<C>
+where
+    T: AsyncDecode,
+    C: Extend<T> + Send,
+    R: AsyncRead + Unpin + Send,
+{
+    ctx.enter()?;
+
+    let mut result = Ok(());
+    for _ in 0..len {
+        match T::decode_async_with(ctx).await {
+            Ok(item) => items.extend(Some(item)),
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    ctx.leave();
+    result.map(|()| items)
+}
+
+impl<T: AsyncDecode> AsyncDecode for Vec<T> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        let len = Var::<u32>::decode_async_with(ctx).await?.0 as usize;
+        ctx.check_collection_len(len)?;
+
+        let buf = Vec::with_capacity(ctx.capacity_for::<T>(len));
+        decode_items(ctx, len, buf).await
+    }
+}
+
+impl<T: AsyncDecode, const N: usize> AsyncDecode for [T; N] {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode_async_with(ctx).await?);
+        }
+
+        match items.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!("decoded exactly N items"),
+        }
+    }
+}
+
+/// Decodes a length-prefixed map or set, rejecting duplicate keys.
+async fn decode_unique<T, C, R>(ctx: &mut DecodeContext<R>) -> Result<C>
+where
+    T: AsyncDecode,
+    C: Default + Extend<T> + Unique + Send,
+    R: AsyncRead + Unpin + Send,
+{
+    let len = Var::<u32>::decode_async_with(ctx).await?.0 as usize;
+    ctx.check_collection_len(len)?;
+    let collection = decode_items(ctx, len, C::default()).await?;
+    check_unique(collection, len)
+}
+
+impl<K, V, S> AsyncDecode for HashMap<K, V, S>
+where
+    K: AsyncDecode + Eq + Hash,
+    V: AsyncDecode,
+    S: BuildHasher + Default + Send,
+{
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        decode_unique(ctx).await
+    }
+}
+
+impl<K: AsyncDecode + Ord, V: AsyncDecode> AsyncDecode for BTreeMap<K, V> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        decode_unique(ctx).await
+    }
+}
+
+impl<T, S> AsyncDecode for HashSet<T, S>
+where
+    T: AsyncDecode + Eq + Hash,
+    S: BuildHasher + Default + Send,
+{
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        decode_unique(ctx).await
+    }
+}
+
+impl<T: AsyncDecode + Ord> AsyncDecode for BTreeSet<T> {
+    async fn decode_async_with<R: AsyncRead + Unpin + Send>(
+        ctx: &mut DecodeContext<R>,
+    ) -> Result<Self> {
+        decode_unique(ctx).await
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{check_unique, BitSet, Decode, DecodeContext, ErrorKind, Limits, Result, Unique, Var};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+/// Decodes a value that may borrow from the buffer it is decoded out of.
+///
+/// This reads the same wire format as [Decode], but `&'de str` and
+/// `&'de [u8]` fields point straight into the input instead of being copied
+/// into new allocations, so a whole datagram can be parsed without
+/// allocating at all.
+pub trait DecodeRef<'de>: Sized {
+    /// Decodes a value within the [Limits] carried by `ctx`.
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self>;
+
+    /// Decodes a value from the front of `buf` with the default [Limits] for
+    /// network input, advancing `buf` past it.
+    fn decode_ref(buf: &mut &'de [u8]) -> Result<Self> {
+        let mut ctx = DecodeContext::new(*buf, Limits::default());
+        let value = Self::decode_ref_with(&mut ctx)?;
+        *buf = ctx.into_inner();
+        Ok(value)
+    }
+}
+
+/// Types that never borrow are decoded exactly like [Decode] does.
+macro_rules! impl_owned (
+    ($($type: ty),+) => (
+        $(
+            impl<'de> DecodeRef<'de> for $type {
+                fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+                    <$type as Decode>::decode_with(ctx)
+                }
+            }
+        )+
+    )
+);
+
+impl_owned!(bool, u8, i8, u16, u32, u64, u128, i16, i32, i64, i128, f32, f64);
+impl_owned!((), char, String);
+
+impl<'de, T> DecodeRef<'de> for Var<T>
+where
+    Var<T>: Decode,
+{
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        Self::decode_with(ctx)
+    }
+}
+
+impl<'de, const N: usize> DecodeRef<'de> for BitSet<N> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        Self::decode_with(ctx)
+    }
+}
+
+impl<'de: 'a, 'a> DecodeRef<'de> for &'a str {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+        ctx.check_string_len(len)?;
+        let bytes = ctx.read_borrowed(len)?;
+        std::str::from_utf8(bytes).map_err(|_| ErrorKind::InvalidUtf8.into())
+    }
+}
+
+/// Encoded like a `Vec<u8>`.
+impl<'de: 'a, 'a> DecodeRef<'de> for &'a [u8] {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+        ctx.check_collection_len(len)?;
+        ctx.read_borrowed(len)
+    }
+}
+
+impl<'de, T: DecodeRef<'de>> DecodeRef<'de> for Box<T> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        ctx.nested(|ctx| Ok(Box::new(T::decode_ref_with(ctx)?)))
+    }
+}
+
+impl<'de, T: DecodeRef<'de>> DecodeRef<'de> for Option<T> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        if bool::decode_with(ctx)? {
+            ctx.nested(|ctx| Ok(Some(T::decode_ref_with(ctx)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! impl_tuple (
+    ($($name: ident),+) => (
+        impl<'de, $($name: DecodeRef<'de>),+> DecodeRef<'de> for ($($name,)+) {
+            fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+                Ok(($($name::decode_ref_with(ctx)?,)+))
+            }
+        }
+    )
+);
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+impl<'de, T: DecodeRef<'de>> DecodeRef<'de> for Vec<T> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+        ctx.check_collection_len(len)?;
+
+        let mut buf = Vec::with_capacity(ctx.capacity_for::<T>(len));
+
+        ctx.nested(|ctx| {
+            for _ in 0..len {
+                buf.push(T::decode_ref_with(ctx)?);
+            }
+
+            Ok(buf)
+        })
+    }
+}
+
+impl<'de, T: DecodeRef<'de>, const N: usize> DecodeRef<'de> for [T; N] {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode_ref_with(ctx)?);
+        }
+
+        match items.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!("decoded exactly N items"),
+        }
+    }
+}
+
+/// Decodes a length-prefixed map or set of borrowed items, rejecting
+/// duplicate keys.
+fn decode_unique<'de, T: DecodeRef<'de>, C: FromIterator<T> + Unique>(
+    ctx: &mut DecodeContext<&'de [u8]>,
+) -> Result<C> {
+    let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+    ctx.check_collection_len(len)?;
+    let collection = ctx.nested(|ctx| (0..len).map(|_| T::decode_ref_with(ctx)).collect())?;
+    check_unique(collection, len)
+}
+
+impl<'de, K, V, S> DecodeRef<'de> for HashMap<K, V, S>
+where
+    K: DecodeRef<'de> + Eq + Hash,
+    V: DecodeRef<'de>,
+    S: BuildHasher + Default,
+{
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<'de, K: DecodeRef<'de> + Ord, V: DecodeRef<'de>> DecodeRef<'de> for BTreeMap<K, V> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<'de, T, S> DecodeRef<'de> for HashSet<T, S>
+where
+    T: DecodeRef<'de> + Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<'de, T: DecodeRef<'de> + Ord> DecodeRef<'de> for BTreeSet<T> {
+    fn decode_ref_with(ctx: &mut DecodeContext<&'de [u8]>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
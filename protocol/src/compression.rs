@@ -0,0 +1,45 @@
+//! LZ4 compression of packet bodies, enabled by the `lz4` feature.
+//!
+//! A compressed body is its uncompressed length as a `Var<u32>` followed by
+//! a single LZ4 block. The length is checked against [Limits::max_bytes]
+//! before anything is allocated, so a small datagram can't decompress into
+//! more than a peer would be allowed to send uncompressed.
+
+use crate::{Decode, Encode, ErrorKind, Limit, Limits, Result, Var};
+
+/// Bodies shorter than this are never compressed, since they rarely shrink
+/// by enough to be worth it.
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Compresses `body`, or returns `None` if it's shorter than
+/// [COMPRESSION_THRESHOLD] or wouldn't get any shorter.
+pub fn compress(body: &[u8]) -> Option<Vec<u8>> {
+    if body.len() < COMPRESSION_THRESHOLD {
+        return None;
+    }
+
+    let len = Var(u32::try_from(body.len()).ok()?);
+    let block = lz4_flex::block::compress(body);
+
+    let mut buf = Vec::with_capacity(len.encoded_len() + block.len());
+    len.encode(&mut buf).ok()?;
+    buf.extend_from_slice(&block);
+    (buf.len() < body.len()).then_some(buf)
+}
+
+/// Decompresses a body produced by [compress], failing if it would be longer
+/// than `limits` allow.
+pub fn decompress(buf: &[u8], limits: &Limits) -> Result<Vec<u8>> {
+    let mut block = buf;
+    let len = Var::<u32>::decode(&mut block)?.0 as usize;
+    if len > limits.max_bytes {
+        return Err(ErrorKind::LimitExceeded(Limit::Bytes).into());
+    }
+
+    let mut body = vec![0u8; len];
+    match lz4_flex::block::decompress_into(block, &mut body) {
+        Ok(written) if written == len => Ok(body),
+        Ok(_) => Err(ErrorKind::Malformed("compressed body shorter than its length").into()),
+        Err(_) => Err(ErrorKind::Malformed("invalid LZ4 block").into()),
+    }
+}
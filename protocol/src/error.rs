@@ -0,0 +1,172 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error encoding or decoding a value.
+///
+/// Derived implementations record which field failed, so that the error
+/// displays as e.g. `RoomInfo.title: invalid UTF-8`.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    ty: Option<&'static str>,
+    /// Field names from the innermost outwards.
+    path: Vec<&'static str>,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The input ended before the value was complete.
+    Truncated,
+
+    /// A string was not valid UTF-8.
+    InvalidUtf8,
+
+    /// An enum tag did not match any variant.
+    UnknownDiscriminant(u64),
+
+    /// One of the decoding [crate::Limits] was exceeded.
+    LimitExceeded(Limit),
+
+    /// The input was malformed in some other way.
+    Malformed(&'static str),
+
+    /// A message from a serde `Serialize` or `Deserialize` implementation.
+    Custom(String),
+
+    /// The underlying reader or writer failed.
+    Io(std::io::Error),
+}
+
+/// Which of the [crate::Limits] was exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    StringLength,
+    CollectionLength,
+    Depth,
+    Bytes,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            ty: None,
+            path: Vec::new(),
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// The path to the field that failed, like `RoomInfo.title`.
+    pub fn path(&self) -> Option<String> {
+        let ty = self.ty?;
+        let fields = self.path.iter().rev().copied();
+        Some(
+            std::iter::once(ty)
+                .chain(fields)
+                .collect::<Vec<_>>()
+                .join("."),
+        )
+    }
+
+    /// Records that this error occurred in the given field of `ty`.
+    pub fn in_field(mut self, ty: &'static str, field: &'static str) -> Self {
+        self.ty = Some(ty);
+        self.path.push(field);
+        self
+    }
+
+    /// Records that this error occurred in `ty` itself.
+    pub fn in_type(mut self, ty: &'static str) -> Self {
+        self.ty = Some(ty);
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.path() {
+            Some(path) => write!(f, "{}: {}", path, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            ErrorKind::Truncated => write!(f, "unexpected end of input"),
+            ErrorKind::InvalidUtf8 => write!(f, "invalid UTF-8"),
+            ErrorKind::UnknownDiscriminant(tag) => write!(f, "unknown discriminant {}", tag),
+            ErrorKind::LimitExceeded(limit) => write!(f, "{} limit exceeded", limit),
+            ErrorKind::Malformed(msg) => write!(f, "{}", msg),
+            ErrorKind::Custom(msg) => write!(f, "{}", msg),
+            ErrorKind::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Limit::StringLength => "string length",
+            Limit::CollectionLength => "collection length",
+            Limit::Depth => "nesting depth",
+            Limit::Bytes => "byte",
+        })
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.kind {
+            ErrorKind::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+/// Errors that had to pass through a [std::io::Read] impl, such as
+/// [crate::DecodeContext]'s byte limit, are unwrapped again here.
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *err.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+
+        match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => ErrorKind::Truncated.into(),
+            _ => ErrorKind::Io(err).into(),
+        }
+    }
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error {
+                kind: ErrorKind::Io(err),
+                ty: None,
+                ..
+            } => err,
+            err => {
+                let kind = match err.kind {
+                    ErrorKind::Truncated => std::io::ErrorKind::UnexpectedEof,
+                    ErrorKind::Io(ref inner) => inner.kind(),
+                    _ => std::io::ErrorKind::InvalidData,
+                };
+
+                std::io::Error::new(kind, err)
+            }
+        }
+    }
+}
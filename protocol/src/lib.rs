@@ -1,77 +1,224 @@
 use byteorder::{ReadBytesExt, WriteBytesExt};
 use paste::paste;
-use std::io::{Read, Result as IoResult, Write};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hash};
+use std::io::{Read, Write};
 use std::ops::{Deref, DerefMut};
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod borrow;
+#[cfg(feature = "lz4")]
+pub mod compression;
+mod error;
+mod limits;
+mod schema;
+#[cfg(feature = "serde")]
+pub mod serde;
+#[cfg(feature = "proptest")]
+pub mod testing;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::{AsyncDecode, AsyncEncode};
+pub use borrow::DecodeRef;
+pub use error::{Error, ErrorKind, Limit, Result};
+pub use limits::{DecodeContext, Limits};
+pub use schema::{Describe, FieldDef, Schema, TypeDef, TypeKind, VariantDef};
+
+// for `#[derive(AsyncDecode)]`, so that crates deriving it can name tokio's
+// traits without depending on it themselves
+#[cfg(feature = "tokio")]
+#[doc(hidden)]
+pub use tokio;
+
+/// The largest payload that fits in a single UDP datagram over IPv4.
+pub const MAX_DATAGRAM_LEN: usize = 65507;
+
 pub trait Encode {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()>;
+    fn encode(&self, writer: &mut impl Write) -> Result<()>;
+
+    /// The exact number of bytes that [Encode::encode] writes, computed
+    /// without encoding anything where possible.
+    ///
+    /// This is meant for sizing buffers up front and for checking a packet
+    /// against [MAX_DATAGRAM_LEN] before it's sent. The default
+    /// implementation encodes into a [LenCounter] to find out. If encoding
+    /// would fail, the result is unspecified.
+    fn encoded_len(&self) -> usize {
+        let mut counter = LenCounter::default();
+        let _ = self.encode(&mut counter);
+        counter.0
+    }
 }
 
 pub trait Decode: Sized {
-    fn decode(reader: &mut impl Read) -> IoResult<Self>;
+    /// Decodes a value within the [Limits] carried by `ctx`.
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self>;
+
+    /// Decodes a value with the default [Limits] for network input.
+    fn decode(reader: &mut impl Read) -> Result<Self> {
+        Self::decode_with(&mut DecodeContext::new(reader, Limits::default()))
+    }
 }
 
-// TODO: raw bool encoding is inefficient and should be replaced with bitsets.
+/// A writer that throws away everything written to it, only counting how
+/// many bytes there were.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LenCounter(pub usize);
+
+impl Write for LenCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Derived structs pack their bool fields into a [BitSet] instead, so a lone
+// bool only costs a whole byte when it's encoded by itself.
 impl Encode for bool {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-        writer.write_u8(if *self { 1 } else { 0 })
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        Ok(writer.write_u8(if *self { 1 } else { 0 })?)
+    }
+
+    fn encoded_len(&self) -> usize {
+        1
     }
 }
 
 impl Decode for bool {
-    fn decode(reader: &mut impl Read) -> IoResult<Self> {
-        Ok(reader.read_u8()? != 0)
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        Ok(ctx.read_u8()? != 0)
     }
 }
 
 impl Encode for u8 {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-        writer.write_u8(*self)
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        Ok(writer.write_u8(*self)?)
+    }
+
+    fn encoded_len(&self) -> usize {
+        1
     }
 }
 
 impl Decode for u8 {
-    fn decode(reader: &mut impl Read) -> IoResult<Self> {
-        reader.read_u8()
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        Ok(ctx.read_u8()?)
     }
 }
 
 impl Encode for i8 {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-        writer.write_i8(*self)
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        Ok(writer.write_i8(*self)?)
+    }
+
+    fn encoded_len(&self) -> usize {
+        1
     }
 }
 
 impl Decode for i8 {
-    fn decode(reader: &mut impl Read) -> IoResult<Self> {
-        reader.read_i8()
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        Ok(ctx.read_i8()?)
     }
 }
 
-macro_rules! impl_ordered_int (
+macro_rules! impl_ordered (
     ($type: ident) => (
         impl Encode for $type {
-            fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-                paste! { writer.[<write_ $type>]::<byteorder::LittleEndian>(*self) }
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                paste! { writer.[<write_ $type>]::<byteorder::LittleEndian>(*self)? };
+                Ok(())
+            }
+
+            fn encoded_len(&self) -> usize {
+                std::mem::size_of::<$type>()
             }
         }
 
         impl Decode for $type {
-            fn decode(reader: &mut impl Read) -> IoResult<Self> {
-                paste! { reader.[<read_ $type>]::<byteorder::LittleEndian>() }
+            fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+                paste! { Ok(ctx.[<read_ $type>]::<byteorder::LittleEndian>()?) }
             }
         }
     )
 );
 
-impl_ordered_int!(u16);
-impl_ordered_int!(u32);
-impl_ordered_int!(u64);
-impl_ordered_int!(u128);
-impl_ordered_int!(i16);
-impl_ordered_int!(i32);
-impl_ordered_int!(i64);
-impl_ordered_int!(i128);
+impl_ordered!(u16);
+impl_ordered!(u32);
+impl_ordered!(u64);
+impl_ordered!(u128);
+impl_ordered!(i16);
+impl_ordered!(i32);
+impl_ordered!(i64);
+impl_ordered!(i128);
+impl_ordered!(f32);
+impl_ordered!(f64);
+
+/// A fixed-size set of flags packed into `N` bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BitSet<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> BitSet<N> {
+    /// The number of flags this set can hold.
+    pub const BITS: usize = N * 8;
+
+    pub fn new() -> Self {
+        Self([0; N])
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.0[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        let mask = 1 << (index % 8);
+        if value {
+            self.0[index / 8] |= mask;
+        } else {
+            self.0[index / 8] &= !mask;
+        }
+    }
+
+    /// Fails if any flag from `len` on is set, so that a set holding `len`
+    /// flags has exactly one encoding.
+    pub fn check_len(&self, len: usize) -> Result<()> {
+        if (len..Self::BITS).any(|index| self.get(index)) {
+            return Err(ErrorKind::Malformed("unused bits set in bitset").into());
+        }
+
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for BitSet<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Encode for BitSet<N> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.0)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> Decode for BitSet<N> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        let mut bits = Self::new();
+        ctx.read_exact(&mut bits.0)?;
+        Ok(bits)
+    }
+}
 
 #[repr(transparent)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -90,97 +237,483 @@ impl<T> DerefMut for Var<T> {
     }
 }
 
+/// Varints come straight off the network, so only the canonical encoding of
+/// each value is accepted: decoding fails on a zero-valued final byte after
+/// the first (an overlong encoding) and on any bits past the type's width.
 macro_rules! impl_var_uint (
     ($type: ident) => (
         impl Encode for Var<$type> {
-            fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
                 let mut value = self.0;
 
                 loop {
                     if (value & !0x7f) == 0 {
-                        writer.write(&[value as u8])?;
+                        writer.write_all(&[value as u8])?;
                         return Ok(());
                     }
 
                     let next = (value as u8 & 0x7f) | 0x80;
-                    writer.write(&[next])?;
+                    writer.write_all(&[next])?;
                     value >>= 7;
                 }
             }
+
+            fn encoded_len(&self) -> usize {
+                var_len(self.0 as u128)
+            }
         }
 
         impl Decode for Var<$type> {
-            fn decode(reader: &mut impl Read) -> IoResult<Self> {
+            fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
                 let mut value = 0;
                 let mut position = 0;
 
-                while position < $type::BITS {
-                    let b = reader.read_u8()?;
-                    value |= (b as $type & 0x7f) << position;
+                loop {
+                    let b = ctx.read_u8()?;
+                    let bits = b as $type & 0x7f;
+
+                    if position > 0 && b == 0 {
+                        return Err(ErrorKind::Malformed("overlong varint").into());
+                    }
+
+                    if position + 7 > $type::BITS && bits >> ($type::BITS - position) != 0 {
+                        return Err(ErrorKind::Malformed("varint overflows its type").into());
+                    }
+
+                    value |= bits << position;
 
                     if (b & 0x80) == 0 {
-                        break;
+                        return Ok(Var(value));
                     }
 
                     position += 7;
-                }
 
-                Ok(Var(value))
+                    if position >= $type::BITS {
+                        return Err(ErrorKind::Malformed("varint overflows its type").into());
+                    }
+                }
             }
         }
     )
 );
 
+/// The number of bytes in the varint encoding of `value`.
+fn var_len(value: u128) -> usize {
+    let bits = u128::BITS - value.leading_zeros();
+    bits.div_ceil(7).max(1) as usize
+}
+
 impl_var_uint!(u16);
 impl_var_uint!(u32);
 impl_var_uint!(u64);
 impl_var_uint!(u128);
 
-impl Encode for String {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-        let len = Var::<u32>(self.len().try_into().unwrap());
-        len.encode(writer)?;
+/// Signed integers are ZigZag-encoded so that small negative numbers stay
+/// small on the wire: 0, -1, 1, -2, 2... map to 0, 1, 2, 3, 4...
+macro_rules! impl_var_int (
+    ($type: ident, $unsigned: ident) => (
+        impl Encode for Var<$type> {
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                Var(self.zigzag()).encode(writer)
+            }
+
+            fn encoded_len(&self) -> usize {
+                Var(self.zigzag()).encoded_len()
+            }
+        }
+
+        impl Var<$type> {
+            fn zigzag(&self) -> $unsigned {
+                ((self.0 << 1) ^ (self.0 >> ($type::BITS - 1))) as $unsigned
+            }
+        }
+
+        impl Decode for Var<$type> {
+            fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+                let zigzag = Var::<$unsigned>::decode_with(ctx)?.0;
+                Ok(Var((zigzag >> 1) as $type ^ -((zigzag & 1) as $type)))
+            }
+        }
+    )
+);
+
+impl_var_int!(i16, u16);
+impl_var_int!(i32, u32);
+impl_var_int!(i64, u64);
+impl_var_int!(i128, u128);
+
+impl Encode for () {
+    fn encode(&self, _writer: &mut impl Write) -> Result<()> {
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> usize {
+        0
+    }
+}
+
+impl Decode for () {
+    fn decode_with(_ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        Var(*self as u32).encode(writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        Var(*self as u32).encoded_len()
+    }
+}
+
+impl Decode for char {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        let code = Var::<u32>::decode_with(ctx)?.0;
+        char::from_u32(code).ok_or_else(|| ErrorKind::Malformed("invalid char").into())
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        (**self).encode(writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        (**self).encode(writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        ctx.nested(|ctx| Ok(Box::new(T::decode_with(ctx)?)))
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        match self {
+            None => false.encode(writer),
+            Some(value) => {
+                true.encode(writer)?;
+                value.encode(writer)
+            }
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            None => 1,
+            Some(value) => 1 + value.encoded_len(),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        if bool::decode_with(ctx)? {
+            ctx.nested(|ctx| Ok(Some(T::decode_with(ctx)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+macro_rules! impl_tuple (
+    ($($name: ident),+) => (
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                $($name.encode(writer)?;)+
+                Ok(())
+            }
+
+            fn encoded_len(&self) -> usize {
+                #[allow(non_snake_case)]
+                let ($($name,)+) = self;
+                0 $(+ $name.encoded_len())+
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+                Ok(($($name::decode_with(ctx)?,)+))
+            }
+        }
+    )
+);
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// Encodes the length prefix of a string or collection.
+pub(crate) fn encode_len(len: usize, limit: Limit, writer: &mut impl Write) -> Result<()> {
+    match u32::try_from(len) {
+        Ok(len) => Var(len).encode(writer),
+        Err(_) => Err(ErrorKind::LimitExceeded(limit).into()),
+    }
+}
+
+/// The encoded size of a length prefix.
+fn len_len(len: usize) -> usize {
+    var_len(len as u128)
+}
+
+impl Encode for str {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_len(self.len(), Limit::StringLength, writer)?;
         writer.write_all(self.as_bytes())?;
         Ok(())
     }
+
+    fn encoded_len(&self) -> usize {
+        len_len(self.len()) + self.len()
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.as_str().encode(writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.as_str().encoded_len()
+    }
 }
 
 impl Decode for String {
-    fn decode(reader: &mut impl Read) -> IoResult<Self> {
-        let len = Var::<u32>::decode(reader)?;
-        let mut buf = vec![0u8; len.0 as usize];
-        reader.read_exact(&mut buf)?;
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+        ctx.check_string_len(len)?;
+        let mut buf = vec![0u8; len];
+        ctx.read_exact(&mut buf)?;
 
         if let Ok(string) = String::from_utf8(buf) {
             Ok(string)
         } else {
-            Err(std::io::ErrorKind::InvalidData.into())
+            Err(ErrorKind::InvalidUtf8.into())
         }
     }
 }
 
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(self.len(), self.iter(), writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        seq_len(self.len(), self.iter())
+    }
+}
+
 impl<T: Encode> Encode for Vec<T> {
-    fn encode(&self, writer: &mut impl Write) -> IoResult<()> {
-        let len = Var::<u32>(self.len().try_into().unwrap());
-        len.encode(writer)?;
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.as_slice().encode(writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.as_slice().encoded_len()
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+        ctx.check_collection_len(len)?;
+
+        let mut buf = Vec::with_capacity(ctx.capacity_for::<T>(len));
+
+        ctx.nested(|ctx| {
+            for _ in 0..len {
+                buf.push(T::decode_with(ctx)?);
+            }
+
+            Ok(buf)
+        })
+    }
+}
 
+/// Arrays have a fixed length, so unlike slices they have no length prefix.
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
         for item in self.iter() {
             item.encode(writer)?;
         }
 
         Ok(())
     }
+
+    fn encoded_len(&self) -> usize {
+        self.iter().map(Encode::encoded_len).sum()
+    }
 }
 
-impl<T: Decode> Decode for Vec<T> {
-    fn decode(reader: &mut impl Read) -> IoResult<Self> {
-        let len = Var::<u32>::decode(reader)?;
-        let mut buf = Vec::with_capacity(len.0 as usize);
-        for _ in 0..len.0 {
-            buf.push(T::decode(reader)?);
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode_with(ctx)?);
+        }
+
+        match items.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!("decoded exactly N items"),
         }
+    }
+}
+
+/// Encodes a length-prefixed sequence of items.
+fn encode_seq<T: Encode>(
+    len: usize,
+    items: impl Iterator<Item = T>,
+    writer: &mut impl Write,
+) -> Result<()> {
+    encode_len(len, Limit::CollectionLength, writer)?;
+
+    for item in items {
+        item.encode(writer)?;
+    }
+
+    Ok(())
+}
+
+/// The encoded size of a length-prefixed sequence of items.
+fn seq_len<T: Encode>(len: usize, items: impl Iterator<Item = T>) -> usize {
+    len_len(len) + items.map(|item| item.encoded_len()).sum::<usize>()
+}
+
+/// Maps and sets, which hold each key at most once.
+pub(crate) trait Unique {
+    fn unique_len(&self) -> usize;
+}
+
+impl<K, V, S> Unique for HashMap<K, V, S> {
+    fn unique_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V> Unique for BTreeMap<K, V> {
+    fn unique_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T, S> Unique for HashSet<T, S> {
+    fn unique_len(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> Unique for BTreeSet<T> {
+    fn unique_len(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Fails if fewer than the `len` items decoded into `collection` were kept,
+/// so that each map or set has exactly one encoding.
+pub(crate) fn check_unique<C: Unique>(collection: C, len: usize) -> Result<C> {
+    if collection.unique_len() != len {
+        return Err(ErrorKind::Malformed("duplicate key").into());
+    }
+
+    Ok(collection)
+}
+
+/// Decodes a length-prefixed map or set, rejecting duplicate keys.
+fn decode_unique<T: Decode, C: FromIterator<T> + Unique>(
+    ctx: &mut DecodeContext<impl Read>,
+) -> Result<C> {
+    let len = Var::<u32>::decode_with(ctx)?.0 as usize;
+    ctx.check_collection_len(len)?;
+    let collection = ctx.nested(|ctx| (0..len).map(|_| T::decode_with(ctx)).collect())?;
+    check_unique(collection, len)
+}
+
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(self.len(), self.iter(), writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        seq_len(self.len(), self.iter())
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode, S: BuildHasher + Default> Decode for HashMap<K, V, S> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(self.len(), self.iter(), writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        seq_len(self.len(), self.iter())
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<T: Encode, S> Encode for HashSet<T, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(self.len(), self.iter(), writer)
+    }
 
-        Ok(buf)
+    fn encoded_len(&self) -> usize {
+        seq_len(self.len(), self.iter())
+    }
+}
+
+impl<T: Decode + Eq + Hash, S: BuildHasher + Default> Decode for HashSet<T, S> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        decode_unique(ctx)
+    }
+}
+
+impl<T: Encode> Encode for BTreeSet<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(self.len(), self.iter(), writer)
+    }
+
+    fn encoded_len(&self) -> usize {
+        seq_len(self.len(), self.iter())
+    }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    fn decode_with(ctx: &mut DecodeContext<impl Read>) -> Result<Self> {
+        decode_unique(ctx)
     }
 }
 
@@ -190,8 +723,7 @@ mod tests {
     use core::fmt::Debug;
 
     fn test_roundtrip<T: Debug + Eq + Encode + Decode>(original: T) {
-        let mut buf = Vec::new();
-        original.encode(&mut buf).unwrap();
+        let buf = encode(&original);
         let mut reader = buf.as_slice();
         let decoded = T::decode(&mut reader).unwrap();
         assert_eq!(original, decoded, "Round-trip encoded values do not match!");
@@ -265,6 +797,587 @@ mod tests {
         test_var_int!(u32);
         test_var_int!(u64);
         test_var_int!(u128);
+        test_var_int!(i16);
+        test_var_int!(i32);
+        test_var_int!(i64);
+        test_var_int!(i128);
+
+        #[test]
+        fn zigzag() {
+            assert_eq!(encode(&Var(0i32)), [0]);
+            assert_eq!(encode(&Var(-1i32)), [1]);
+            assert_eq!(encode(&Var(1i32)), [2]);
+            assert_eq!(encode(&Var(-2i32)), [3]);
+            assert_eq!(encode(&Var(-64i32)), [0x7f]);
+            assert_eq!(encode(&Var(64i32)), [0x80, 0x01]);
+            assert_eq!(encode(&Var(i16::MIN)), [0xff, 0xff, 0x03]);
+        }
+
+        fn decode_var<T>(buf: &[u8]) -> Result<T>
+        where
+            Var<T>: Decode,
+        {
+            let mut reader = buf;
+            let value = Var::<T>::decode(&mut reader)?.0;
+            assert!(reader.is_empty(), "Trailing bytes after decoding!");
+            Ok(value)
+        }
+
+        fn assert_invalid<T: Debug>(result: Result<T>) {
+            let err = result.unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Malformed(_)), "{:?}", err);
+        }
+
+        macro_rules! test_var_boundaries (
+            ($type: ident) => (
+                mod $type {
+                    use super::*;
+
+                    const MAX_LEN: usize = ($type::BITS as usize).div_ceil(7);
+
+                    #[test]
+                    fn boundaries() {
+                        for len in 1..MAX_LEN {
+                            let max: $type = (1 << (7 * len)) - 1;
+                            assert_eq!(encode(&Var(max)).len(), len);
+                            assert_eq!(encode(&Var(max + 1)).len(), len + 1);
+                            test_roundtrip(Var(max));
+                            test_roundtrip(Var(max + 1));
+                        }
+
+                        assert_eq!(encode(&Var($type::MAX)).len(), MAX_LEN);
+                    }
+
+                    #[test]
+                    fn overlong() {
+                        for len in 2..=MAX_LEN {
+                            let mut buf = vec![0x80; len];
+                            buf[len - 1] = 0x00;
+                            assert_invalid(decode_var::<$type>(&buf));
+
+                            // value 1 padded out with a zero continuation
+                            buf[0] = 0x81;
+                            assert_invalid(decode_var::<$type>(&buf));
+                        }
+                    }
+
+                    #[test]
+                    fn too_long() {
+                        let mut buf = vec![0xff; MAX_LEN + 1];
+                        buf[MAX_LEN] = 0x01;
+                        assert_invalid(decode_var::<$type>(&buf));
+
+                        let mut buf = vec![0x80; MAX_LEN + 1];
+                        buf[MAX_LEN] = 0x01;
+                        assert_invalid(decode_var::<$type>(&buf));
+                    }
+
+                    #[test]
+                    fn overflow() {
+                        let spare = 7 * MAX_LEN as u32 - $type::BITS;
+                        let top = 0x7f >> spare;
+
+                        let mut buf = encode(&Var($type::MAX));
+                        assert_eq!(buf[MAX_LEN - 1], top);
+
+                        for extra in 1..=spare {
+                            buf[MAX_LEN - 1] = top | (1 << (7 - extra));
+                            assert_invalid(decode_var::<$type>(&buf));
+                        }
+                    }
+
+                    #[test]
+                    fn truncated() {
+                        let buf = encode(&Var($type::MAX));
+                        for len in 0..buf.len() {
+                            let err = decode_var::<$type>(&buf[..len]).unwrap_err();
+                            assert!(matches!(err.kind(), ErrorKind::Truncated));
+                        }
+                    }
+                }
+            )
+        );
+
+        mod strict {
+            use super::*;
+
+            test_var_boundaries!(u16);
+            test_var_boundaries!(u32);
+            test_var_boundaries!(u64);
+            test_var_boundaries!(u128);
+
+            /// Every input that decodes must be the one canonical encoding
+            /// of its value.
+            #[test]
+            fn u16_canonical() {
+                for b0 in 0..=255u8 {
+                    for b1 in 0..=255u8 {
+                        for b2 in [0x00, 0x01, 0x02, 0x03, 0x04, 0x7f, 0x80, 0xff] {
+                            let buf = [b0, b1, b2];
+                            let mut reader = buf.as_slice();
+                            if let Ok(value) = Var::<u16>::decode(&mut reader) {
+                                let used = buf.len() - reader.len();
+                                assert_eq!(encode(&value), &buf[..used]);
+                            }
+                        }
+                    }
+                }
+            }
+
+            #[test]
+            fn u16_exhaustive() {
+                for value in 0..=u16::MAX {
+                    assert_eq!(decode_var::<u16>(&encode(&Var(value))).unwrap(), value);
+                }
+            }
+        }
+
+        /// The length of the shortest varint that can hold `bits` bits.
+        fn minimal_len(bits: u32) -> usize {
+            (bits.max(1) as usize).div_ceil(7)
+        }
+
+        macro_rules! proptest_var_int (
+            ($type: ident, $unsigned: ident) => (
+                mod $type {
+                    use super::*;
+                    use proptest::prelude::*;
+
+                    proptest! {
+                        #[test]
+                        fn roundtrip(value: $type) {
+                            test_roundtrip(Var(value));
+                        }
+
+                        #[test]
+                        fn minimal(value: $type) {
+                            let zigzag = ((value << 1) ^ (value >> ($type::BITS - 1))) as $unsigned;
+                            let bits = $unsigned::BITS - zigzag.leading_zeros();
+                            prop_assert_eq!(encode(&Var(value)).len(), minimal_len(bits));
+                        }
+
+                        #[test]
+                        fn unsigned_roundtrip(value: $unsigned) {
+                            test_roundtrip(Var(value));
+                        }
+
+                        #[test]
+                        fn unsigned_minimal(value: $unsigned) {
+                            let bits = $unsigned::BITS - value.leading_zeros();
+                            prop_assert_eq!(encode(&Var(value)).len(), minimal_len(bits));
+                        }
+                    }
+                }
+            )
+        );
+
+        mod prop {
+            use super::*;
+
+            proptest_var_int!(i16, u16);
+            proptest_var_int!(i32, u32);
+            proptest_var_int!(i64, u64);
+            proptest_var_int!(i128, u128);
+        }
+    }
+
+    /// Encodes `value`, checking [Encode::encoded_len] along the way.
+    fn encode<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.encode(&mut buf).unwrap();
+        assert_eq!(value.encoded_len(), buf.len(), "Wrong encoded length!");
+        buf
+    }
+
+    mod encoded_len {
+        use super::*;
+
+        struct Custom;
+
+        impl Encode for Custom {
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                writer.write_all(b"custom")?;
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn default_counts() {
+            assert_eq!(Custom.encoded_len(), 6);
+            assert_eq!(encode(&vec![Custom, Custom]).len(), 13);
+        }
+
+        #[test]
+        fn length_prefixes() {
+            for len in [0, 1, 127, 128, 16383, 16384, 70000] {
+                encode(&"x".repeat(len));
+                encode(&vec![0u8; len]);
+            }
+        }
+
+        #[test]
+        fn var_boundaries() {
+            for shift in 0..128 {
+                encode(&Var(1u128 << shift));
+                encode(&Var(-(1i128 << shift.min(126))));
+            }
+        }
+    }
+
+    mod float {
+        use super::*;
+
+        fn test_float_roundtrip<T: Debug + PartialEq + Encode + Decode>(original: T) {
+            let buf = encode(&original);
+            let decoded = T::decode(&mut buf.as_slice()).unwrap();
+            assert_eq!(original, decoded, "Round-trip encoded values do not match!");
+        }
+
+        #[test]
+        fn f32() {
+            test_float_roundtrip(f32::MIN);
+            test_float_roundtrip(f32::MAX);
+            test_float_roundtrip(-0.5f32);
+            test_float_roundtrip(f32::INFINITY);
+        }
+
+        #[test]
+        fn f64() {
+            test_float_roundtrip(f64::MIN_POSITIVE);
+            test_float_roundtrip(f64::MAX);
+            test_float_roundtrip(std::f64::consts::PI);
+        }
+
+        #[test]
+        fn nan() {
+            let decoded = f64::decode(&mut encode(&f64::NAN).as_slice()).unwrap();
+            assert!(decoded.is_nan());
+        }
+    }
+
+    mod misc {
+        use super::*;
+
+        #[test]
+        fn unit() {
+            test_roundtrip(());
+            assert!(encode(&()).is_empty());
+        }
+
+        #[test]
+        fn char() {
+            test_roundtrip('a');
+            test_roundtrip('\u{10ffff}');
+            test_roundtrip('🦀');
+        }
+
+        #[test]
+        fn invalid_char() {
+            let buf = encode(&Var(0xd800u32));
+            let err = char::decode(&mut buf.as_slice()).unwrap_err();
+            assert_eq!(err.to_string(), "invalid char");
+        }
+
+        #[test]
+        fn option() {
+            test_roundtrip(None::<u32>);
+            test_roundtrip(Some(5u32));
+            test_roundtrip(Some(Some("nested".to_string())));
+        }
+
+        #[test]
+        fn boxed() {
+            test_roundtrip(Box::new(42u64));
+        }
+
+        #[test]
+        fn tuple() {
+            test_roundtrip((1u8,));
+            test_roundtrip((1u8, "two".to_string(), Var(3u32)));
+            test_roundtrip((
+                1u8,
+                2u16,
+                3u32,
+                4u64,
+                5u128,
+                6i8,
+                7i16,
+                8i32,
+                9i64,
+                10i128,
+                (),
+                'c',
+            ));
+        }
+
+        #[test]
+        fn array() {
+            test_roundtrip([0u8; 0]);
+            test_roundtrip([1u16, 2, 3]);
+            assert_eq!(encode(&[1u8, 2, 3]), [1, 2, 3]);
+        }
+
+        #[test]
+        fn borrowed() {
+            assert_eq!(encode("borrowed"), encode(&"borrowed".to_string()));
+            assert_eq!(encode(&[1u8, 2][..]), encode(&vec![1u8, 2]));
+            assert_eq!(encode(&&5u32), encode(&5u32));
+        }
+    }
+
+    mod collections {
+        use super::*;
+
+        #[test]
+        fn vec() {
+            test_roundtrip(Vec::<u8>::new());
+            test_roundtrip(vec!["a".to_string(), "b".to_string()]);
+        }
+
+        #[test]
+        fn hash_map() {
+            let map: HashMap<String, u32> = [("a".to_string(), 1), ("b".to_string(), 2)].into();
+            test_roundtrip(map);
+        }
+
+        #[test]
+        fn btree_map() {
+            let map: BTreeMap<u8, String> = [(1, "a".to_string()), (2, "b".to_string())].into();
+            assert_eq!(encode(&map), [2, 1, 1, b'a', 2, 1, b'b']);
+            test_roundtrip(map);
+        }
+
+        #[test]
+        fn hash_set() {
+            test_roundtrip(HashSet::from([1u16, 2, 3]));
+        }
+
+        #[test]
+        fn btree_set() {
+            test_roundtrip(BTreeSet::from(['x', 'y']));
+        }
+
+        #[test]
+        fn duplicate_keys() {
+            fn assert_duplicate<T: Decode + Debug>(buf: &[u8]) {
+                let err = T::decode(&mut &buf[..]).unwrap_err();
+                assert!(matches!(err.kind(), ErrorKind::Malformed("duplicate key")));
+            }
+
+            assert_duplicate::<HashMap<u8, u8>>(&[2, 1, 10, 1, 20]);
+            assert_duplicate::<BTreeMap<u8, u8>>(&[2, 1, 10, 1, 20]);
+            assert_duplicate::<HashSet<u8>>(&[3, 1, 2, 1]);
+            assert_duplicate::<BTreeSet<u8>>(&[2, 7, 7]);
+        }
+    }
+
+    mod limits {
+        use super::*;
+
+        fn decode_limited<T: Decode>(buf: &[u8], limits: Limits) -> Result<T> {
+            T::decode_with(&mut DecodeContext::new(buf, limits))
+        }
+
+        fn assert_limited<T: Debug>(result: Result<T>, limit: Limit) {
+            match result.unwrap_err().kind() {
+                ErrorKind::LimitExceeded(exceeded) => assert_eq!(*exceeded, limit),
+                kind => panic!("expected {:?} limit error, got {:?}", limit, kind),
+            }
+        }
+
+        #[test]
+        fn huge_string() {
+            // a five-byte packet claiming a 4 GiB string
+            let buf = encode(&Var(u32::MAX));
+            assert_limited(String::decode(&mut buf.as_slice()), Limit::StringLength);
+        }
+
+        #[test]
+        fn huge_vec() {
+            let buf = encode(&Var(u32::MAX));
+            assert_limited(
+                Vec::<u64>::decode(&mut buf.as_slice()),
+                Limit::CollectionLength,
+            );
+            assert_limited(
+                Vec::<()>::decode(&mut buf.as_slice()),
+                Limit::CollectionLength,
+            );
+            assert_limited(
+                HashMap::<u8, u8>::decode(&mut buf.as_slice()),
+                Limit::CollectionLength,
+            );
+        }
+
+        #[test]
+        fn string_len() {
+            let limits = Limits {
+                max_string_len: 4,
+                ..Limits::NETWORK
+            };
+
+            let buf = encode("four");
+            assert_eq!(decode_limited::<String>(&buf, limits).unwrap(), "four");
+
+            let buf = encode("five!");
+            assert_limited(decode_limited::<String>(&buf, limits), Limit::StringLength);
+        }
+
+        #[test]
+        fn collection_len() {
+            let limits = Limits {
+                max_collection_len: 2,
+                ..Limits::NETWORK
+            };
+
+            let buf = encode(&vec![1u8, 2]);
+            assert_eq!(decode_limited::<Vec<u8>>(&buf, limits).unwrap(), [1, 2]);
+
+            let buf = encode(&vec![1u8, 2, 3]);
+            assert_limited(
+                decode_limited::<Vec<u8>>(&buf, limits),
+                Limit::CollectionLength,
+            );
+            assert_limited(
+                decode_limited::<BTreeSet<u8>>(&buf, limits),
+                Limit::CollectionLength,
+            );
+        }
+
+        #[test]
+        fn depth() {
+            let limits = Limits {
+                max_depth: 3,
+                ..Limits::NETWORK
+            };
+
+            let value = vec![vec![vec![1u8]]];
+            let buf = encode(&value);
+            assert_eq!(
+                decode_limited::<Vec<Vec<Vec<u8>>>>(&buf, limits).unwrap(),
+                value
+            );
+
+            let buf = encode(&Some(Some(Some(Some(1u8)))));
+            assert_limited(
+                decode_limited::<Option<Option<Option<Option<u8>>>>>(&buf, limits),
+                Limit::Depth,
+            );
+        }
+
+        #[test]
+        fn bytes() {
+            let limits = Limits {
+                max_bytes: 5,
+                ..Limits::NETWORK
+            };
+
+            let buf = encode(&0u32);
+            assert_eq!(decode_limited::<u32>(&buf, limits).unwrap(), 0);
+
+            let buf = encode(&(0u32, 0u16));
+            assert_limited(decode_limited::<(u32, u16)>(&buf, limits), Limit::Bytes);
+
+            let buf = encode("longer than five bytes");
+            assert_limited(decode_limited::<String>(&buf, limits), Limit::StringLength);
+        }
+
+        #[test]
+        fn capacity() {
+            let ctx = DecodeContext::new([0u8; 3].as_slice(), Limits::NETWORK);
+            assert_eq!(ctx.capacity_for::<u8>(10), 10);
+            assert_eq!(ctx.capacity_for::<u8>(100_000), MAX_DATAGRAM_LEN);
+            assert_eq!(ctx.capacity_for::<[u8; 1024]>(MAX_DATAGRAM_LEN), 63);
+            assert_eq!(ctx.capacity_for::<()>(5), 5);
+
+            let ctx = DecodeContext::new([0u8; 3].as_slice(), Limits::UNLIMITED);
+            assert_eq!(ctx.capacity_for::<u64>(usize::MAX), 64 * 1024 / 8);
+        }
+
+        #[test]
+        fn bounded() {
+            let buf = [1, 2, 3, 4, 5];
+            let mut ctx = DecodeContext::new(buf.as_slice(), Limits::NETWORK);
+            let first = ctx.bounded(3, |ctx| {
+                assert_eq!(ctx.remaining(), 3);
+                u8::decode_with(ctx)
+            });
+
+            // the rest of the body is skipped
+            assert_eq!(first.unwrap(), 1);
+            assert_eq!(u8::decode_with(&mut ctx).unwrap(), 4);
+            assert_eq!(ctx.remaining(), Limits::NETWORK.max_bytes - 4);
+
+            let mut ctx = DecodeContext::new(buf.as_slice(), Limits::NETWORK);
+            let err = ctx.bounded(1, u16::decode_with).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Truncated));
+
+            let mut ctx = DecodeContext::new(buf.as_slice(), Limits::NETWORK);
+            let err = ctx.bounded(6, |_| Ok(())).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Truncated));
+
+            let limits = Limits {
+                max_bytes: 2,
+                ..Limits::NETWORK
+            };
+
+            let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+            assert_limited(ctx.bounded(3, |_| Ok(())), Limit::Bytes);
+        }
+
+        #[test]
+        fn unlimited() {
+            let value = vec!["a".repeat(70000)];
+            let buf = encode(&value);
+            assert_limited(
+                Vec::<String>::decode(&mut buf.as_slice()),
+                Limit::StringLength,
+            );
+            assert_eq!(
+                decode_limited::<Vec<String>>(&buf, Limits::UNLIMITED).unwrap(),
+                value
+            );
+        }
+    }
+
+    mod bitset {
+        use super::*;
+
+        #[test]
+        fn set_and_get() {
+            let mut bits = BitSet::<2>::new();
+            bits.set(0, true);
+            bits.set(9, true);
+            bits.set(15, true);
+            bits.set(15, false);
+
+            assert!(bits.get(0));
+            assert!(!bits.get(1));
+            assert!(bits.get(9));
+            assert!(!bits.get(15));
+            assert_eq!(bits.0, [0b0000_0001, 0b0000_0010]);
+        }
+
+        #[test]
+        fn empty() {
+            test_roundtrip(BitSet::<1>::new());
+        }
+
+        #[test]
+        fn full() {
+            test_roundtrip(BitSet([0xff; 4]));
+        }
+
+        #[test]
+        fn check_len() {
+            let bits = BitSet([0b0000_0101, 0]);
+            assert!(bits.check_len(3).is_ok());
+            assert!(bits.check_len(16).is_ok());
+
+            let err = bits.check_len(2).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Malformed(_)));
+            assert!(BitSet([0, 0b1000_0000]).check_len(9).is_err());
+        }
     }
 
     mod string {
@@ -280,4 +1393,152 @@ mod tests {
             test_roundtrip("".to_string());
         }
     }
+
+    mod borrowed {
+        use super::*;
+
+        fn decode_ref<'de, T: DecodeRef<'de>>(mut buf: &'de [u8]) -> Result<T> {
+            let value = T::decode_ref(&mut buf)?;
+            assert!(buf.is_empty(), "Trailing bytes after decoding!");
+            Ok(value)
+        }
+
+        #[test]
+        fn str_borrows() {
+            let buf = encode(&"hello");
+            let decoded: &str = decode_ref(&buf).unwrap();
+            assert_eq!(decoded, "hello");
+            assert!(buf.as_ptr_range().contains(&decoded.as_ptr()));
+        }
+
+        #[test]
+        fn bytes_match_vec() {
+            let buf = encode(&vec![1u8, 2, 3]);
+            let decoded: &[u8] = decode_ref(&buf).unwrap();
+            assert_eq!(decoded, [1, 2, 3]);
+        }
+
+        #[test]
+        fn nested() {
+            let original = (Some("a"), vec!["b", "c"], Var(300u32), 'd');
+            let buf = encode(&original);
+            let decoded: (Option<&str>, Vec<&str>, Var<u32>, char) = decode_ref(&buf).unwrap();
+            assert_eq!(original, decoded);
+
+            let map: BTreeMap<&str, u8> = [("x", 1), ("y", 2)].into_iter().collect();
+            let buf = encode(&map);
+            assert_eq!(map, decode_ref(&buf).unwrap());
+        }
+
+        #[test]
+        fn duplicate_keys() {
+            let buf = [2, 1, b'x', 1, 1, b'x', 2];
+            let err = decode_ref::<HashMap<&str, u8>>(&buf).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Malformed("duplicate key")));
+        }
+
+        #[test]
+        fn advances() {
+            let buf = encode(&("first", "second"));
+            let mut reader = buf.as_slice();
+            assert_eq!(<&str>::decode_ref(&mut reader).unwrap(), "first");
+            assert_eq!(<&str>::decode_ref(&mut reader).unwrap(), "second");
+            assert!(reader.is_empty());
+        }
+
+        #[test]
+        fn invalid_utf8() {
+            let err = decode_ref::<&str>(&[2, 0xc3, 0x28]).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::InvalidUtf8));
+        }
+
+        #[test]
+        fn truncated() {
+            let err = decode_ref::<&str>(&[5, b'a', b'b']).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Truncated));
+        }
+
+        #[test]
+        fn limits() {
+            let buf = encode(&"hello");
+            let limits = Limits {
+                max_string_len: 4,
+                ..Limits::NETWORK
+            };
+
+            let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+            let err = <&str>::decode_ref_with(&mut ctx).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                ErrorKind::LimitExceeded(Limit::StringLength)
+            ));
+
+            let buf = encode(&vec![0u8; 8]);
+            let limits = Limits {
+                max_bytes: 4,
+                ..Limits::NETWORK
+            };
+
+            let mut ctx = DecodeContext::new(buf.as_slice(), limits);
+            let err = <&[u8]>::decode_ref_with(&mut ctx).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    mod compression {
+        use super::*;
+        use crate::compression::*;
+
+        #[test]
+        fn roundtrip() {
+            let body = "a long about text that repeats itself. ".repeat(16);
+            let compressed = compress(body.as_bytes()).unwrap();
+            assert!(compressed.len() < body.len());
+
+            let decompressed = decompress(&compressed, &Limits::NETWORK).unwrap();
+            assert_eq!(decompressed, body.as_bytes());
+        }
+
+        #[test]
+        fn threshold() {
+            assert!(compress(&[0; COMPRESSION_THRESHOLD - 1]).is_none());
+            assert!(compress(&[0; COMPRESSION_THRESHOLD]).is_some());
+        }
+
+        #[test]
+        fn incompressible() {
+            // xorshift, so that there's nothing for LZ4 to find
+            let mut state = 0x2545f491u32;
+            let body: Vec<u8> = (0..1024)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect();
+            assert!(compress(&body).is_none());
+        }
+
+        #[test]
+        fn bomb() {
+            let compressed = compress(&vec![0; MAX_DATAGRAM_LEN * 4]).unwrap();
+            assert!(compressed.len() < MAX_DATAGRAM_LEN);
+
+            let err = decompress(&compressed, &Limits::NETWORK).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::LimitExceeded(Limit::Bytes)));
+        }
+
+        #[test]
+        fn malformed() {
+            let mut compressed = compress(&[7; 512]).unwrap();
+            compressed.truncate(compressed.len() - 1);
+            let err = decompress(&compressed, &Limits::NETWORK).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Malformed(_)));
+
+            let err = decompress(&[], &Limits::NETWORK).unwrap_err();
+            assert!(matches!(err.kind(), ErrorKind::Truncated));
+        }
+    }
 }
@@ -0,0 +1,296 @@
+use crate::{Error, ErrorKind, Limit, Result};
+use std::io::{Read, Result as IoResult};
+
+/// The most memory, in bytes, reserved for a collection before its items
+/// have actually been decoded.
+const MAX_RESERVE: usize = 64 * 1024;
+
+/// Bounds on the resources that decoding untrusted input may consume.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum length of a single string, in bytes.
+    pub max_string_len: usize,
+
+    /// Maximum number of items in a single collection.
+    pub max_collection_len: usize,
+
+    /// Maximum nesting depth of derived types and collections.
+    pub max_depth: usize,
+
+    /// Maximum number of bytes read over the whole decode.
+    pub max_bytes: usize,
+}
+
+impl Limits {
+    /// Limits for decoding a single UDP datagram from an untrusted peer.
+    pub const NETWORK: Self = Self {
+        max_string_len: crate::MAX_DATAGRAM_LEN,
+        max_collection_len: crate::MAX_DATAGRAM_LEN,
+        max_depth: 32,
+        max_bytes: crate::MAX_DATAGRAM_LEN,
+    };
+
+    /// No limits at all, for trusted input only.
+    pub const UNLIMITED: Self = Self {
+        max_string_len: usize::MAX,
+        max_collection_len: usize::MAX,
+        max_depth: usize::MAX,
+        max_bytes: usize::MAX,
+    };
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::NETWORK
+    }
+}
+
+/// A reader that enforces [Limits] while a value is being decoded.
+///
+/// Reads past [Limits::max_bytes] fail, and [crate::Decode] implementations
+/// check lengths and nesting against the limits before allocating anything.
+pub struct DecodeContext<R> {
+    reader: R,
+    limits: Limits,
+    depth: usize,
+    remaining: usize,
+    /// Whether `remaining` is the end of a body rather than of the limit.
+    bounded: bool,
+}
+
+/// What [DecodeContext::enter_body] saved of the context around a body.
+#[doc(hidden)]
+pub struct Body {
+    outer: usize,
+    bounded: bool,
+}
+
+impl<R> DecodeContext<R> {
+    pub fn new(reader: R, limits: Limits) -> Self {
+        Self {
+            reader,
+            limits,
+            depth: 0,
+            remaining: limits.max_bytes,
+            bounded: false,
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// The number of bytes that may still be read.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Checks a decoded string length before its buffer is allocated.
+    pub fn check_string_len(&self, len: usize) -> Result<()> {
+        if len > self.limits.max_string_len {
+            Err(limit_exceeded(Limit::StringLength))
+        } else if len > self.remaining && self.bounded {
+            Err(self.exhausted())
+        } else if len > self.remaining {
+            Err(limit_exceeded(Limit::StringLength))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks a decoded collection length before any items are decoded.
+    pub fn check_collection_len(&self, len: usize) -> Result<()> {
+        if len > self.limits.max_collection_len {
+            Err(limit_exceeded(Limit::CollectionLength))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// How many items of type `T` to reserve room for up front when a
+    /// collection claims to hold `len` of them.
+    ///
+    /// Every item takes at least a byte, barring degenerate types, so the
+    /// claimed length isn't trusted past the bytes left to read. The
+    /// reservation is kept to [MAX_RESERVE] bytes on top of that, since an
+    /// item may take far more memory than bytes on the wire.
+    pub(crate) fn capacity_for<T>(&self, len: usize) -> usize {
+        let bytes = self.remaining.min(MAX_RESERVE);
+        len.min(bytes / std::mem::size_of::<T>().max(1))
+    }
+
+    /// Goes one nesting level deeper, failing past [Limits::max_depth].
+    ///
+    /// Every `enter` must be paired with a [DecodeContext::leave], which
+    /// [DecodeContext::nested] does for synchronous decoding.
+    #[doc(hidden)]
+    pub fn enter(&mut self) -> Result<()> {
+        if self.depth >= self.limits.max_depth {
+            return Err(limit_exceeded(Limit::Depth));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    pub fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Starts reading a body of exactly `len` bytes, returning what to
+    /// restore once it's been read. [DecodeContext::bounded] does both for
+    /// synchronous decoding.
+    #[doc(hidden)]
+    pub fn enter_body(&mut self, len: usize) -> Result<Body> {
+        if len > self.remaining {
+            return Err(self.exhausted());
+        }
+
+        let body = Body {
+            outer: self.remaining - len,
+            bounded: std::mem::replace(&mut self.bounded, true),
+        };
+
+        self.remaining = len;
+        Ok(body)
+    }
+
+    /// Ends a body once all of it has been read or skipped.
+    fn leave_body(&mut self, body: Body) {
+        self.remaining = body.outer;
+        self.bounded = body.bounded;
+    }
+
+    /// The error for reading past the last byte that may be read.
+    fn exhausted(&self) -> Error {
+        if self.bounded {
+            ErrorKind::Truncated.into()
+        } else {
+            limit_exceeded(Limit::Bytes)
+        }
+    }
+}
+
+impl<R: Read> DecodeContext<R> {
+    /// Runs `f` one nesting level deeper, failing past [Limits::max_depth].
+    pub fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.enter()?;
+        let result = f(self);
+        self.leave();
+        result
+    }
+
+    /// Runs `f` over a body of exactly `len` bytes, skipping over whatever
+    /// `f` leaves unread.
+    ///
+    /// Within the body, [DecodeContext::remaining] only counts the bytes left
+    /// in it, and reading past its end fails as truncated input.
+    pub fn bounded<T>(&mut self, len: usize, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let body = self.enter_body(len)?;
+
+        let result = f(self).and_then(|value| {
+            self.skip(self.remaining)?;
+            Ok(value)
+        });
+
+        self.leave_body(body);
+        result
+    }
+
+    /// Reads and discards `len` bytes.
+    fn skip(&mut self, mut len: usize) -> Result<()> {
+        let mut buf = [0u8; 256];
+        while len > 0 {
+            let chunk = len.min(buf.len());
+            self.read_exact(&mut buf[..chunk])?;
+            len -= chunk;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: tokio::io::AsyncRead + Unpin> DecodeContext<R> {
+    /// Fills `buf` from the reader, the asynchronous counterpart of
+    /// [Read::read_exact].
+    pub async fn read_exact_async(&mut self, buf: &mut [u8]) -> Result<()> {
+        if buf.len() > self.remaining {
+            return Err(self.exhausted());
+        }
+
+        tokio::io::AsyncReadExt::read_exact(&mut self.reader, buf).await?;
+        self.remaining -= buf.len();
+        Ok(())
+    }
+
+    /// Ends a body from [DecodeContext::enter_body] with the `result` of
+    /// decoding it, skipping over whatever was left unread.
+    #[doc(hidden)]
+    pub async fn leave_body_async<T>(&mut self, body: Body, result: Result<T>) -> Result<T> {
+        let result = match result {
+            Ok(value) => self.skip_async(self.remaining).await.map(|()| value),
+            Err(err) => Err(err),
+        };
+
+        self.leave_body(body);
+        result
+    }
+
+    async fn skip_async(&mut self, mut len: usize) -> Result<()> {
+        let mut buf = [0u8; 256];
+        while len > 0 {
+            let chunk = len.min(buf.len());
+            self.read_exact_async(&mut buf[..chunk]).await?;
+            len -= chunk;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> DecodeContext<&'a [u8]> {
+    /// Takes the next `len` bytes of the input without copying them.
+    pub fn read_borrowed(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.remaining {
+            return Err(self.exhausted());
+        }
+
+        if len > self.reader.len() {
+            return Err(ErrorKind::Truncated.into());
+        }
+
+        let (bytes, rest) = self.reader.split_at(len);
+        self.reader = rest;
+        self.remaining -= len;
+        Ok(bytes)
+    }
+}
+
+impl<R: Read> Read for DecodeContext<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // passed back out through Error's From<std::io::Error> impl
+        if self.remaining == 0 {
+            let err = self.exhausted();
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err));
+        }
+
+        let len = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..len])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+fn limit_exceeded(limit: Limit) -> Error {
+    ErrorKind::LimitExceeded(limit).into()
+}
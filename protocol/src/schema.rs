@@ -0,0 +1,546 @@
+use crate::{BitSet, Var};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write;
+
+/// A type whose wire format can be described in a [Schema].
+pub trait Describe {
+    /// The name of this type in a schema, like `var<u32>`, `list<string>`
+    /// or `RoomInfo`.
+    fn type_name() -> String;
+
+    /// Adds the definitions of this type and of every type it contains to
+    /// `schema`.
+    ///
+    /// Built-in types are described by [Schema::PRELUDE] instead, so by
+    /// default this does nothing.
+    fn define(_schema: &mut Schema) {}
+}
+
+/// Definitions of a set of types, in the order they were first reached.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schema {
+    pub types: Vec<TypeDef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeDef {
+    pub name: String,
+    pub doc: Option<String>,
+    pub kind: TypeKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeKind {
+    Struct {
+        /// Whether the fields are prefixed by their length.
+        extensible: bool,
+        fields: Vec<FieldDef>,
+    },
+    Enum {
+        variants: Vec<VariantDef>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariantDef {
+    pub name: String,
+    pub doc: Option<String>,
+    pub tag: u16,
+    pub fields: Vec<FieldDef>,
+}
+
+/// A field that is encoded on the wire, in declaration order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldDef {
+    /// The field's name, or its index in a tuple struct or variant.
+    pub name: String,
+    pub doc: Option<String>,
+
+    /// The [Describe::type_name] of the field, or its Rust type if it is
+    /// encoded `with` a custom module.
+    pub ty: String,
+
+    /// The bit holding this field in the leading bitset, for packed bools.
+    pub bit: Option<usize>,
+
+    /// The version this field was added in, in an extensible struct.
+    pub since: Option<u32>,
+
+    /// The module that encodes this field in its own format.
+    pub with: Option<String>,
+}
+
+impl Schema {
+    /// How the built-in types are encoded, as a Markdown list.
+    pub const PRELUDE: &'static str = "\
+- `bool`, `u8`, `i8`: a single byte. Bools are 0 or 1.
+- `u16`, `u32`, `u64`, `u128`, `i16`, `i32`, `i64`, `i128`, `f32`, `f64`: \
+little-endian, fixed width.
+- `var<uN>`: an unsigned LEB128 varint, 7 bits per byte with the high bit \
+set on all but the last. Only the shortest encoding is valid.
+- `var<iN>`: ZigZag-encoded (0, -1, 1, -2... as 0, 1, 2, 3...) into a `var<uN>`.
+- `char`: a Unicode scalar value as a `var<u32>`.
+- `unit`: nothing at all.
+- `string`: a `var<u32>` length in bytes followed by that much UTF-8.
+- `list<T>`, `set<T>`: a `var<u32>` count followed by that many `T`s.
+- `map<K, V>`: a `var<u32>` count followed by that many `K` and `V` pairs.
+- `array<T, N>`: exactly `N` `T`s, with no length prefix.
+- `tuple<A, B, ...>`: each item in order.
+- `option<T>`: a `bool` followed by a `T` if it is 1.
+- `bitset<N>`: `N` bytes of flags, where flag `i` is bit `i % 8` of byte `i / 8`.
+
+Struct fields are encoded in order, except that fields with a `bit` are \
+packed into a leading `bitset` just large enough to hold them all. \
+Extensible structs are prefixed by a `var<u32>` length in bytes of the rest \
+of their fields: fields added `since` a version may be missing from the end, \
+and anything following the known fields is skipped.
+
+Enums are encoded as a `var<u16>` tag, followed by the fields of the variant \
+with that tag like a struct.
+";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The schema of `T` and of every type it contains.
+    pub fn of<T: Describe + ?Sized>() -> Self {
+        let mut schema = Self::new();
+        T::define(&mut schema);
+        schema
+    }
+
+    /// Adds `def` unless a type of the same name is already defined,
+    /// returning whether it was added.
+    pub fn insert(&mut self, def: TypeDef) -> bool {
+        if self.get(&def.name).is_some() {
+            return false;
+        }
+
+        self.types.push(def);
+        true
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TypeDef> {
+        self.types.iter().find(|def| def.name == name)
+    }
+
+    /// Describes every type as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        let types = self.types.iter().map(TypeDef::to_json).collect();
+        let mut out = String::new();
+        Json::Object(vec![("types", Json::Array(types))]).write(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    /// Describes every type as a Markdown document, starting with the
+    /// [Schema::PRELUDE].
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# Wire format\n\n");
+        out.push_str(Self::PRELUDE);
+
+        for def in self.types.iter() {
+            let _ = write!(out, "\n## `{}`\n\n", def.name);
+
+            if let Some(ref doc) = def.doc {
+                let _ = write!(out, "{}\n\n", doc);
+            }
+
+            match def.kind {
+                TypeKind::Struct {
+                    extensible,
+                    ref fields,
+                } => {
+                    if extensible {
+                        out.push_str("Extensible struct.\n\n");
+                    } else {
+                        out.push_str("Struct.\n\n");
+                    }
+
+                    write_fields(&mut out, fields);
+                }
+                TypeKind::Enum { ref variants } => {
+                    out.push_str("Enum.\n\n| Tag | Variant | Fields |\n| --- | --- | --- |\n");
+
+                    for v in variants.iter() {
+                        let fields: Vec<_> = v
+                            .fields
+                            .iter()
+                            .map(|f| match f.bit {
+                                Some(bit) => format!("{}: `{}` (bit {})", f.name, f.ty, bit),
+                                None => format!("{}: `{}`", f.name, f.ty),
+                            })
+                            .collect();
+
+                        let _ =
+                            writeln!(out, "| {} | `{}` | {} |", v.tag, v.name, fields.join(", "));
+                    }
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn write_fields(out: &mut String, fields: &[FieldDef]) {
+    if fields.is_empty() {
+        out.push_str("No fields.\n");
+        return;
+    }
+
+    out.push_str("| Field | Type | Notes |\n| --- | --- | --- |\n");
+
+    for f in fields.iter() {
+        let mut notes = Vec::new();
+
+        if let Some(bit) = f.bit {
+            notes.push(format!("bit {}", bit));
+        }
+
+        if let Some(since) = f.since {
+            notes.push(format!("since {}", since));
+        }
+
+        if let Some(ref with) = f.with {
+            notes.push(format!("custom encoding by `{}`", with));
+        }
+
+        if let Some(ref doc) = f.doc {
+            notes.push(doc.clone());
+        }
+
+        let _ = writeln!(out, "| `{}` | `{}` | {} |", f.name, f.ty, notes.join("; "));
+    }
+}
+
+impl TypeDef {
+    fn to_json(&self) -> Json {
+        let mut members = vec![("name", Json::String(self.name.clone()))];
+        optional(&mut members, "doc", &self.doc, |doc| {
+            Json::String(doc.clone())
+        });
+
+        match self.kind {
+            TypeKind::Struct {
+                extensible,
+                ref fields,
+            } => {
+                members.push(("kind", Json::String("struct".into())));
+                members.push(("extensible", Json::Bool(extensible)));
+                members.push(("fields", fields_json(fields)));
+            }
+            TypeKind::Enum { ref variants } => {
+                let variants = variants
+                    .iter()
+                    .map(|v| {
+                        let mut members = vec![
+                            ("name", Json::String(v.name.clone())),
+                            ("tag", Json::Number(v.tag.into())),
+                        ];
+
+                        optional(&mut members, "doc", &v.doc, |doc| Json::String(doc.clone()));
+                        members.push(("fields", fields_json(&v.fields)));
+                        Json::Object(members)
+                    })
+                    .collect();
+
+                members.push(("kind", Json::String("enum".into())));
+                members.push(("variants", Json::Array(variants)));
+            }
+        }
+
+        Json::Object(members)
+    }
+}
+
+fn fields_json(fields: &[FieldDef]) -> Json {
+    let fields = fields
+        .iter()
+        .map(|f| {
+            let mut members = vec![
+                ("name", Json::String(f.name.clone())),
+                ("type", Json::String(f.ty.clone())),
+            ];
+
+            optional(&mut members, "doc", &f.doc, |doc| Json::String(doc.clone()));
+            optional(&mut members, "bit", &f.bit, |bit| Json::Number(*bit as u64));
+            optional(&mut members, "since", &f.since, |since| {
+                Json::Number((*since).into())
+            });
+            optional(&mut members, "with", &f.with, |with| {
+                Json::String(with.clone())
+            });
+            Json::Object(members)
+        })
+        .collect();
+
+    Json::Array(fields)
+}
+
+/// Adds a member to a JSON object only if it's present.
+fn optional<T>(
+    members: &mut Vec<(&'static str, Json)>,
+    key: &'static str,
+    value: &Option<T>,
+    f: impl FnOnce(&T) -> Json,
+) {
+    if let Some(value) = value {
+        members.push((key, f(value)));
+    }
+}
+
+/// Just enough JSON to write out a schema.
+enum Json {
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Bool(value) => {
+                let _ = write!(out, "{}", value);
+            }
+            Json::Number(value) => {
+                let _ = write!(out, "{}", value);
+            }
+            Json::String(value) => write_json_string(out, value),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+
+                    newline(out, indent + 1);
+                    item.write(out, indent + 1);
+                }
+
+                newline(out, indent);
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (index, (key, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+
+                    newline(out, indent + 1);
+                    write_json_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                }
+
+                newline(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: usize) {
+    out.push('\n');
+    for _ in 0..indent {
+        out.push_str("  ");
+    }
+}
+
+fn write_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+macro_rules! impl_named (
+    ($($type: ty => $name: expr),+ $(,)?) => (
+        $(
+            impl Describe for $type {
+                fn type_name() -> String {
+                    $name.to_string()
+                }
+            }
+        )+
+    )
+);
+
+impl_named! {
+    bool => "bool",
+    u8 => "u8",
+    i8 => "i8",
+    u16 => "u16",
+    u32 => "u32",
+    u64 => "u64",
+    u128 => "u128",
+    i16 => "i16",
+    i32 => "i32",
+    i64 => "i64",
+    i128 => "i128",
+    f32 => "f32",
+    f64 => "f64",
+    () => "unit",
+    char => "char",
+    str => "string",
+    String => "string",
+    Var<u16> => "var<u16>",
+    Var<u32> => "var<u32>",
+    Var<u64> => "var<u64>",
+    Var<u128> => "var<u128>",
+    Var<i16> => "var<i16>",
+    Var<i32> => "var<i32>",
+    Var<i64> => "var<i64>",
+    Var<i128> => "var<i128>",
+}
+
+impl<const N: usize> Describe for BitSet<N> {
+    fn type_name() -> String {
+        format!("bitset<{}>", N)
+    }
+}
+
+/// References and boxes are encoded just like the value they point to.
+impl<T: Describe + ?Sized> Describe for &T {
+    fn type_name() -> String {
+        T::type_name()
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe + ?Sized> Describe for Box<T> {
+    fn type_name() -> String {
+        T::type_name()
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe> Describe for Option<T> {
+    fn type_name() -> String {
+        format!("option<{}>", T::type_name())
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe> Describe for Vec<T> {
+    fn type_name() -> String {
+        <[T]>::type_name()
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<K: Describe, V: Describe, S> Describe for HashMap<K, V, S> {
+    fn type_name() -> String {
+        BTreeMap::<K, V>::type_name()
+    }
+
+    fn define(schema: &mut Schema) {
+        BTreeMap::<K, V>::define(schema)
+    }
+}
+
+impl<K: Describe, V: Describe> Describe for BTreeMap<K, V> {
+    fn type_name() -> String {
+        format!("map<{}, {}>", K::type_name(), V::type_name())
+    }
+
+    fn define(schema: &mut Schema) {
+        K::define(schema);
+        V::define(schema);
+    }
+}
+
+impl<T: Describe, S> Describe for HashSet<T, S> {
+    fn type_name() -> String {
+        BTreeSet::<T>::type_name()
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe> Describe for BTreeSet<T> {
+    fn type_name() -> String {
+        format!("set<{}>", T::type_name())
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe> Describe for [T] {
+    fn type_name() -> String {
+        format!("list<{}>", T::type_name())
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+impl<T: Describe, const N: usize> Describe for [T; N] {
+    fn type_name() -> String {
+        format!("array<{}, {}>", T::type_name(), N)
+    }
+
+    fn define(schema: &mut Schema) {
+        T::define(schema)
+    }
+}
+
+macro_rules! impl_tuple (
+    ($($name: ident),+) => (
+        impl<$($name: Describe),+> Describe for ($($name,)+) {
+            fn type_name() -> String {
+                let items: Vec<String> = vec![$($name::type_name()),+];
+                format!("tuple<{}>", items.join(", "))
+            }
+
+            fn define(schema: &mut Schema) {
+                $($name::define(schema);)+
+            }
+        }
+    )
+);
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
@@ -0,0 +1,839 @@
+//! A serde [Serializer] and [Deserializer] for the wire format, enabled by
+//! the `serde` feature, so that any `Serialize` type can be sent to peers.
+//!
+//! Values are laid out just as the equivalent [Encode] types are: integers
+//! are fixed-size and little-endian unless wrapped in a [Var], strings and
+//! sequences are prefixed with their length, structs and tuples are their
+//! fields in order, and enum variants are prefixed with their index as a
+//! `Var<u16>` tag. So a struct deriving `Serialize` encodes the same as one
+//! deriving `Encode`, except that:
+//!
+//! - Each `bool` takes a byte of its own. A deserializer can't tell how
+//!   many bools a struct has before it reads it, so they can't be packed.
+//! - There's no equivalent of the `#[protocol(...)]` attributes. Tags are
+//!   always the variant's index, and structs can't be `extensible`.
+//! - Fields that are skipped only sometimes, like with
+//!   `#[serde(skip_serializing_if = "...")]`, can't be decoded again.
+//!
+//! The format isn't self-describing, so types that need
+//! `deserialize_any`, like `serde_json::Value`, aren't supported, and
+//! strings are always decoded into owned `String`s.
+//!
+//! Protocol types like [Var] and [BitSet] implement `Serialize` and
+//! `Deserialize` too, so that values containing them can also be written to
+//! other formats such as JSON for debugging.
+
+use crate::{encode_len, BitSet, Decode, DecodeContext, Encode, Error, ErrorKind, Limit};
+use crate::{Limits, Result, Var};
+use ::serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use ::serde::ser::{self, Serialize};
+use paste::paste;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{Read, Write};
+
+/// The newtype struct name that [Var] serializes as, which tells the
+/// [Serializer] and [Deserializer] to use a varint for the integer inside.
+const VAR: &str = "$protocol::Var";
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        ErrorKind::Custom(msg.to_string()).into()
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        ErrorKind::Custom(msg.to_string()).into()
+    }
+}
+
+/// Encodes `value` to `writer`.
+pub fn to_writer<T: Serialize + ?Sized>(writer: &mut impl Write, value: &T) -> Result<()> {
+    value.serialize(&mut Serializer::new(writer))
+}
+
+/// Encodes `value` into a new buffer.
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    to_writer(&mut buf, value)?;
+    Ok(buf)
+}
+
+/// Decodes a `T` from `reader` with the default [Limits] for network input.
+pub fn from_reader<T: DeserializeOwned>(reader: &mut impl Read) -> Result<T> {
+    T::deserialize(&mut Deserializer::new(reader, Limits::default()))
+}
+
+/// Serializes values in the wire format.
+pub struct Serializer<W> {
+    writer: W,
+    /// Whether the next integer is inside a [Var].
+    var: bool,
+}
+
+impl<W: Write> Serializer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, var: false }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn encode(&mut self, value: &(impl Encode + ?Sized)) -> Result<()> {
+        value.encode(&mut self.writer)
+    }
+
+    fn encode_tag(&mut self, index: u32) -> Result<()> {
+        match u16::try_from(index) {
+            Ok(tag) => self.encode(&Var(tag)),
+            Err(_) => Err(ser::Error::custom("variant index doesn't fit in a tag")),
+        }
+    }
+}
+
+macro_rules! serialize_ints (
+    ($($type: ident),+) => (
+        paste! {
+            $(
+                fn [<serialize_ $type>](self, v: $type) -> Result<()> {
+                    if std::mem::take(&mut self.var) {
+                        self.encode(&Var(v))
+                    } else {
+                        self.encode(&v)
+                    }
+                }
+            )+
+        }
+    )
+);
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    serialize_ints!(u16, u32, u64, u128, i16, i32, i64, i128);
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.encode(&v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.encode(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.encode(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.encode(&false)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.encode(&true)?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.encode_tag(index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if name != VAR {
+            return value.serialize(self);
+        }
+
+        self.var = true;
+        value.serialize(&mut *self)?;
+
+        if std::mem::take(&mut self.var) {
+            Err(ser::Error::custom("Var can only contain integers"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.encode_tag(index)?;
+        value
+            .serialize(self)
+            .map_err(|err| err.in_field(name, variant))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        let len =
+            len.ok_or_else(|| <Error as ser::Error>::custom("sequences must have a known length"))?;
+        encode_len(len, Limit::CollectionLength, &mut self.writer)?;
+        Ok(Compound::new(self, ""))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(Compound::new(self, ""))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Ok(Compound::new(self, name))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.encode_tag(index)?;
+        Ok(Compound::variant(self, name, variant))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        let len =
+            len.ok_or_else(|| <Error as ser::Error>::custom("maps must have a known length"))?;
+        encode_len(len, Limit::CollectionLength, &mut self.writer)?;
+        Ok(Compound::new(self, ""))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(Compound::new(self, name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.encode_tag(index)?;
+        Ok(Compound::variant(self, name, variant))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Serializes the items of a sequence or map, or the fields of a struct,
+/// tuple or variant, one after another.
+pub struct Compound<'a, W> {
+    ser: &'a mut Serializer<W>,
+    ty: &'static str,
+    variant: Option<&'static str>,
+}
+
+impl<'a, W: Write> Compound<'a, W> {
+    fn new(ser: &'a mut Serializer<W>, ty: &'static str) -> Self {
+        Self {
+            ser,
+            ty,
+            variant: None,
+        }
+    }
+
+    fn variant(ser: &'a mut Serializer<W>, ty: &'static str, variant: &'static str) -> Self {
+        Self {
+            ser,
+            ty,
+            variant: Some(variant),
+        }
+    }
+
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let result = value.serialize(&mut *self.ser);
+        match self.variant {
+            Some(variant) => result.map_err(|err| err.in_field(self.ty, variant)),
+            None => result,
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let result = value.serialize(&mut *self.ser);
+        match self.variant {
+            Some(variant) => {
+                result.map_err(|err| err.in_field(variant, key).in_field(self.ty, variant))
+            }
+            None => result.map_err(|err| err.in_field(self.ty, key)),
+        }
+    }
+}
+
+impl<W: Write> ser::SerializeSeq for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTuple for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTupleStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeTupleVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeMap for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.item(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeStruct for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> ser::SerializeStructVariant for Compound<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Deserializes values from the wire format within the [Limits] of a
+/// [DecodeContext].
+pub struct Deserializer<R> {
+    ctx: DecodeContext<R>,
+    /// Whether the next integer is inside a [Var].
+    var: bool,
+}
+
+impl<R: Read> Deserializer<R> {
+    pub fn new(reader: R, limits: Limits) -> Self {
+        Self {
+            ctx: DecodeContext::new(reader, limits),
+            var: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.ctx.into_inner()
+    }
+
+    fn decode<T: Decode>(&mut self) -> Result<T> {
+        T::decode_with(&mut self.ctx)
+    }
+
+    fn decode_len(&mut self) -> Result<usize> {
+        let len = self.decode::<Var<u32>>()?.0 as usize;
+        self.ctx.check_collection_len(len)?;
+        Ok(len)
+    }
+
+    /// Runs `f` one nesting level deeper, failing past [Limits::max_depth].
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.ctx.enter()?;
+        let result = f(self);
+        self.ctx.leave();
+        result
+    }
+}
+
+macro_rules! deserialize_ints (
+    ($($type: ident),+) => (
+        paste! {
+            $(
+                fn [<deserialize_ $type>]<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                    let value = if std::mem::take(&mut self.var) {
+                        self.decode::<Var<$type>>()?.0
+                    } else {
+                        self.decode::<$type>()?
+                    };
+
+                    visitor.[<visit_ $type>](value)
+                }
+            )+
+        }
+    )
+);
+
+impl<'de, R: Read> de::Deserializer<'de> for &mut Deserializer<R> {
+    type Error = Error;
+
+    deserialize_ints!(u16, u32, u64, u128, i16, i32, i64, i128);
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom(
+            "the wire format isn't self-describing, so the type to decode must be known",
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_bool(self.decode()?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.decode()?)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(self.decode()?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(self.decode()?)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(self.decode()?)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_char(self.decode()?)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.decode()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.decode()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.decode()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.decode()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.decode()? {
+            self.nested(|de| visitor.visit_some(de))
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        if name != VAR {
+            return visitor.visit_newtype_struct(self);
+        }
+
+        self.var = true;
+        let value = visitor.visit_newtype_struct(&mut *self)?;
+
+        if std::mem::take(&mut self.var) {
+            Err(de::Error::custom("Var can only contain integers"))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.decode_len()?;
+        self.nested(|de| visitor.visit_seq(Access::new(de, len)))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access::new(self, len))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.nested(|de| visitor.visit_seq(Access::new(de, len)))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.decode_len()?;
+        self.nested(|de| visitor.visit_map(Access::new(de, len)))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.nested(|de| visitor.visit_seq(Access::fields(de, name, fields)))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let tag = self.decode::<Var<u16>>()?.0;
+        let variant = match variants.get(tag as usize) {
+            Some(variant) => *variant,
+            None => {
+                let kind = ErrorKind::UnknownDiscriminant(tag.into());
+                return Err(Error::new(kind).in_type(name));
+            }
+        };
+
+        self.nested(|de| {
+            visitor
+                .visit_enum(Enum { de, tag, variant })
+                .map_err(|err| err.in_field(name, variant))
+        })
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom("identifiers aren't encoded"))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(de::Error::custom(
+            "the wire format isn't self-describing, so values can't be skipped",
+        ))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Deserializes the items of a sequence or map, or the fields of a struct or
+/// tuple, one after another.
+struct Access<'a, R> {
+    de: &'a mut Deserializer<R>,
+    len: usize,
+    index: usize,
+    ty: &'static str,
+    fields: &'static [&'static str],
+}
+
+impl<'a, R: Read> Access<'a, R> {
+    fn new(de: &'a mut Deserializer<R>, len: usize) -> Self {
+        Self::fields(de, "", &[]).with_len(len)
+    }
+
+    fn fields(
+        de: &'a mut Deserializer<R>,
+        ty: &'static str,
+        fields: &'static [&'static str],
+    ) -> Self {
+        Self {
+            de,
+            len: fields.len(),
+            index: 0,
+            ty,
+            fields,
+        }
+    }
+
+    fn with_len(mut self, len: usize) -> Self {
+        self.len = len;
+        self
+    }
+
+    fn next<'de, T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<T::Value> {
+        let field = self.fields.get(self.index);
+        self.index += 1;
+
+        let result = seed.deserialize(&mut *self.de);
+        match field {
+            Some(field) => result.map_err(|err| err.in_field(self.ty, field)),
+            None => result,
+        }
+    }
+}
+
+impl<'de, R: Read> de::SeqAccess<'de> for Access<'_, R> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.index == self.len {
+            return Ok(None);
+        }
+
+        self.next(seed).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len - self.index)
+    }
+}
+
+impl<'de, R: Read> de::MapAccess<'de> for Access<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.index == self.len {
+            return Ok(None);
+        }
+
+        self.next(seed).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len - self.index)
+    }
+}
+
+/// Deserializes an enum variant once its tag has been decoded.
+struct Enum<'a, R> {
+    de: &'a mut Deserializer<R>,
+    tag: u16,
+    variant: &'static str,
+}
+
+impl<'de, R: Read> de::EnumAccess<'de> for Enum<'_, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index: de::value::U32Deserializer<Error> = u32::from(self.tag).into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de, R: Read> de::VariantAccess<'de> for Enum<'_, R> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Access::new(self.de, len))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_seq(Access::fields(self.de, self.variant, fields))
+    }
+}
+
+macro_rules! impl_var (
+    ($($type: ident),+) => (
+        $(
+            impl Serialize for Var<$type> {
+                fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                    serializer.serialize_newtype_struct(VAR, &self.0)
+                }
+            }
+
+            impl<'de> de::Deserialize<'de> for Var<$type> {
+                fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+                    deserializer.deserialize_newtype_struct(VAR, VarVisitor::<$type>(Default::default()))
+                }
+            }
+        )+
+    )
+);
+
+impl_var!(u16, u32, u64, u128, i16, i32, i64, i128);
+
+struct VarVisitor<T>(std::marker::PhantomData<T>);
+
+impl<'de, T: de::Deserialize<'de>> Visitor<'de> for VarVisitor<T> {
+    type Value = Var<T>;
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "an integer")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Self::Value, D::Error> {
+        T::deserialize(deserializer).map(Var)
+    }
+}
+
+/// Serialized as a tuple of its bytes, which the wire format encodes with no
+/// length prefix, just as [Encode] does.
+impl<const N: usize> Serialize for BitSet<N> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for byte in self.0.iter() {
+            tuple.serialize_element(byte)?;
+        }
+
+        tuple.end()
+    }
+}
+
+impl<'de, const N: usize> de::Deserialize<'de> for BitSet<N> {
+    fn deserialize<D: de::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_tuple(N, BitSetVisitor)
+    }
+}
+
+struct BitSetVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for BitSetVisitor<N> {
+    type Value = BitSet<N>;
+
+    fn expecting(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} bytes", N)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Self::Value, A::Error> {
+        let mut bits = BitSet::new();
+        for (index, byte) in bits.0.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+        }
+
+        Ok(bits)
+    }
+}
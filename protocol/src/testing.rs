@@ -0,0 +1,129 @@
+//! Property-based testing of encodings, enabled by the `proptest` feature.
+//!
+//! Types deriving `Encode` and `Decode` can also derive `Arbitrary` from
+//! `protocol-derive` to be generated by [proptest](mod@proptest), and then be checked with
+//! [check_roundtrip] and [check_truncated]:
+//!
+//! ```ignore
+//! #[derive(Debug, PartialEq, Decode, Encode)]
+//! #[cfg_attr(test, derive(Arbitrary))]
+//! struct Message {
+//!     sender: String,
+//!     contents: String,
+//! }
+//!
+//! #[test]
+//! fn message() {
+//!     check_roundtrip::<Message>();
+//!     check_truncated::<Message>();
+//! }
+//! ```
+
+use crate::{BitSet, Decode, Encode, Var};
+use proptest::array::UniformArrayStrategy;
+use proptest::prelude::*;
+use proptest::strategy::Map;
+use proptest::test_runner::{TestCaseError, TestRunner};
+
+// the derive refers to proptest through here, so that crates deriving
+// `Arbitrary` don't need to depend on it themselves
+#[doc(hidden)]
+pub use proptest;
+
+/// The most prefixes of a single encoding that [check_truncated] decodes.
+pub const MAX_CUTS: usize = 64;
+
+impl<T: Arbitrary> Arbitrary for Var<T> {
+    type Parameters = T::Parameters;
+    type Strategy = Map<T::Strategy, fn(T) -> Self>;
+
+    fn arbitrary_with(args: Self::Parameters) -> Self::Strategy {
+        any_with::<T>(args).prop_map(Var)
+    }
+}
+
+impl<const N: usize> Arbitrary for BitSet<N> {
+    type Parameters = ();
+    type Strategy =
+        Map<UniformArrayStrategy<<u8 as Arbitrary>::Strategy, [u8; N]>, fn([u8; N]) -> Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        proptest::array::uniform(any::<u8>()).prop_map(BitSet)
+    }
+}
+
+/// Checks that arbitrary values of `T` decode back to themselves once
+/// encoded, and that [Encode::encoded_len] is exact for them.
+///
+/// Panics with the simplest failing value it can find otherwise.
+pub fn check_roundtrip<T>()
+where
+    T: Arbitrary + Encode + Decode + PartialEq,
+{
+    run::<T>(|value| {
+        let buf = try_encode(value)?;
+        prop_assert_eq!(buf.len(), value.encoded_len(), "wrong encoded_len");
+
+        let mut reader = buf.as_slice();
+        let decoded = T::decode(&mut reader).map_err(|err| TestCaseError::fail(err.to_string()))?;
+        prop_assert!(reader.is_empty(), "trailing bytes after decoding");
+        prop_assert_eq!(&decoded, value);
+        Ok(())
+    });
+}
+
+/// Checks that no strict prefix of the encoding of an arbitrary `T` decodes,
+/// as would be the case for a datagram cut short. Decoding must fail cleanly
+/// instead of panicking or producing some other value.
+///
+/// Long encodings are cut at up to [MAX_CUTS] evenly spaced points, always
+/// including the one just before their last byte, to keep this from taking
+/// quadratic time.
+pub fn check_truncated<T>()
+where
+    T: Arbitrary + Encode + Decode,
+{
+    run::<T>(|value| {
+        let buf = try_encode(value)?;
+
+        let step = (buf.len() / MAX_CUTS).max(1);
+        let cuts = (0..buf.len()).step_by(step).chain(buf.len().checked_sub(1));
+
+        for len in cuts {
+            let result = T::decode(&mut &buf[..len]);
+            prop_assert!(
+                result.is_err(),
+                "decoded {} of {} bytes: {:?}",
+                len,
+                buf.len(),
+                result.ok()
+            );
+        }
+
+        Ok(())
+    });
+}
+
+/// Encodes `value`, panicking if it fails or if [Encode::encoded_len] isn't
+/// exact for it.
+pub fn encode(value: &impl Encode) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.encoded_len());
+    value.encode(&mut buf).unwrap();
+    assert_eq!(buf.len(), value.encoded_len(), "wrong encoded_len");
+    buf
+}
+
+fn try_encode(value: &impl Encode) -> Result<Vec<u8>, TestCaseError> {
+    let mut buf = Vec::new();
+    value
+        .encode(&mut buf)
+        .map_err(|err| TestCaseError::fail(format!("failed to encode: {}", err)))?;
+    Ok(buf)
+}
+
+fn run<T: Arbitrary>(test: impl Fn(&T) -> Result<(), TestCaseError>) {
+    let mut runner = TestRunner::default();
+    if let Err(err) = runner.run(&any::<T>(), |value| test(&value)) {
+        panic!("{}: {}", std::any::type_name::<T>(), err);
+    }
+}
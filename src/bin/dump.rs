@@ -0,0 +1,66 @@
+use clap::Parser;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::exit;
+use udp_mud::capture::{CaptureReader, Direction};
+use udp_mud::datagram;
+
+#[derive(Parser, Debug)]
+#[clap(about = "Pretty-prints a capture of udp-mud datagrams.")]
+struct Args {
+    /// Capture written by `udp-mud --capture`.
+    path: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let file = File::open(&args.path).unwrap_or_else(|err| {
+        eprintln!("failed to open {}: {}", args.path.display(), err);
+        exit(1);
+    });
+
+    let mut capture = CaptureReader::new(BufReader::new(file)).unwrap_or_else(|err| {
+        eprintln!("failed to read {}: {}", args.path.display(), err);
+        exit(1);
+    });
+
+    let mut start = None;
+    loop {
+        let record = match capture.read() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("capture ends with a broken record: {}", err);
+                exit(1);
+            }
+        };
+
+        // timestamps are shown relative to the first record, in seconds
+        let elapsed = record
+            .timestamp
+            .saturating_sub(*start.get_or_insert(record.timestamp));
+        let arrow = match record.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+
+        print!(
+            "{}.{:06} {} {} ({} bytes)",
+            elapsed / 1_000_000,
+            elapsed % 1_000_000,
+            arrow,
+            record.peer,
+            record.datagram.len()
+        );
+
+        match datagram::decode(&record.datagram) {
+            Ok((header, packet)) => {
+                let compressed = if header.flags.compressed { ", LZ4" } else { "" };
+                println!(" #{}{}\n{:#?}", header.sequence, compressed, packet);
+            }
+            Err(err) => println!(" malformed: {}", err),
+        }
+    }
+}
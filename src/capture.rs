@@ -0,0 +1,156 @@
+use protocol::*;
+use protocol_derive::{Decode, Encode};
+use std::io::{BufRead, Write};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first bytes of every capture file.
+pub const MAGIC: [u8; 8] = *b"UMUDCAP1";
+
+/// Every record fits a datagram of any size, with room to spare.
+const LIMITS: Limits = Limits {
+    max_bytes: 2 * MAX_DATAGRAM_LEN,
+    ..Limits::NETWORK
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Decode, Encode)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A datagram sent to or received from a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Decode, Encode)]
+pub struct Record {
+    /// Microseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub direction: Direction,
+    #[protocol(with = "socket_addr")]
+    pub peer: SocketAddr,
+    /// The whole datagram, header and all.
+    pub datagram: Vec<u8>,
+}
+
+impl Record {
+    /// Records `datagram` as having been sent or received just now.
+    pub fn now(direction: Direction, peer: SocketAddr, datagram: &[u8]) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        Self {
+            timestamp,
+            direction,
+            peer,
+            datagram: datagram.to_vec(),
+        }
+    }
+}
+
+/// Addresses are stored as text, like `127.0.0.1:4000` or `[::1]:4000`.
+mod socket_addr {
+    use protocol::{Decode, DecodeContext, Encode, ErrorKind, Result};
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+
+    pub fn encode(value: &SocketAddr, writer: &mut impl Write) -> Result<()> {
+        value.to_string().encode(writer)
+    }
+
+    pub fn decode(ctx: &mut DecodeContext<impl Read>) -> Result<SocketAddr> {
+        let addr = String::decode_with(ctx)?;
+        addr.parse()
+            .map_err(|_| ErrorKind::Malformed("invalid socket address").into())
+    }
+}
+
+/// Writes a capture of every datagram a node sends and receives.
+pub struct CaptureWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Starts a new capture, writing its [MAGIC] right away.
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&MAGIC)?;
+        Ok(Self { writer })
+    }
+
+    /// Writes and flushes `record`, so that a capture is complete up to the
+    /// moment a node stops, however it stops.
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        let mut buf = Vec::with_capacity(record.encoded_len());
+        record.encode(&mut buf)?;
+        self.writer.write_all(&buf)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the records of a capture written by a [CaptureWriter].
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    /// Opens a capture, checking its [MAGIC].
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(ErrorKind::Malformed("not a udp-mud capture").into());
+        }
+
+        Ok(Self { reader })
+    }
+
+    /// Reads the next record, or `None` at the end of the capture.
+    pub fn read(&mut self) -> Result<Option<Record>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        let mut ctx = DecodeContext::new(&mut self.reader, LIMITS);
+        Record::decode_with(&mut ctx).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 4000));
+        let records = [
+            Record::now(Direction::Sent, peer, &[1, 2, 3]),
+            Record::now(Direction::Received, "[::1]:4001".parse().unwrap(), &[]),
+        ];
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in records.iter() {
+            writer.write(record).unwrap();
+        }
+
+        let buf = writer.into_inner();
+        let mut reader = CaptureReader::new(buf.as_slice()).unwrap();
+        for record in records.iter() {
+            assert_eq!(reader.read().unwrap().as_ref(), Some(record));
+        }
+
+        assert!(reader.read().unwrap().is_none());
+
+        let err = CaptureReader::new(&buf[1..]).err().unwrap();
+        assert_eq!(err.to_string(), "not a udp-mud capture");
+
+        let mut reader = CaptureReader::new(&buf[..buf.len() - 1]).unwrap();
+        reader.read().unwrap();
+        let err = reader.read().unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Truncated));
+    }
+}
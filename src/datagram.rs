@@ -0,0 +1,130 @@
+use crate::Packet;
+use protocol::compression::{compress, decompress};
+use protocol::*;
+use protocol_derive::{Decode, Describe, Encode};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::ops::Range;
+
+/// The first bytes of every datagram.
+pub const MAGIC: [u8; 4] = *b"UMUD";
+
+/// The version of the protocol this build speaks.
+pub const VERSION: u8 = 2;
+
+/// Where [Header::checksum] is in an encoded datagram.
+const CHECKSUM: Range<usize> = 5..9;
+
+/// Precedes the packet in every datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Decode, Describe, Encode)]
+pub struct Header {
+    /// Always "UMUD", to tell datagrams apart from stray traffic on the port.
+    pub magic: [u8; 4],
+
+    /// The protocol version of the sender. Datagrams from other versions
+    /// are dropped.
+    pub version: u8,
+
+    /// CRC32 of the rest of the datagram after this field.
+    pub checksum: u32,
+
+    pub flags: Flags,
+
+    /// Counts up with each datagram the sender sends, wrapping around.
+    pub sequence: u32,
+
+    /// Who sent the datagram, wherever they sent it from.
+    pub sender: PeerId,
+}
+
+/// Identifies a node across changes of address, such as when it roams
+/// between networks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Decode, Describe, Encode)]
+pub struct PeerId(pub u64);
+
+impl Display for PeerId {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Options that apply to the rest of a datagram.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Decode, Describe, Encode)]
+pub struct Flags {
+    /// The packet is compressed, as its `var<u32>` length followed by an LZ4
+    /// block.
+    pub compressed: bool,
+
+    /// The sender can decompress packets, so it may be sent compressed ones.
+    pub accepts_compressed: bool,
+}
+
+/// Encodes `packet` into a datagram, compressing it if `compressed` is set
+/// and the packet is long enough to be worth it.
+pub fn encode(packet: &Packet, sender: PeerId, sequence: u32, compressed: bool) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(packet.encoded_len());
+    packet.encode(&mut body)?;
+
+    let mut header = Header {
+        magic: MAGIC,
+        version: VERSION,
+        checksum: 0,
+        flags: Flags {
+            compressed: false,
+            accepts_compressed: true,
+        },
+        sequence,
+        sender,
+    };
+
+    if compressed {
+        if let Some(compressed) = compress(&body) {
+            header.flags.compressed = true;
+            body = compressed;
+        }
+    }
+
+    let mut buf = Vec::with_capacity(header.encoded_len() + body.len());
+    header.encode(&mut buf)?;
+    buf.extend_from_slice(&body);
+
+    let checksum = crc32fast::hash(&buf[CHECKSUM.end..]);
+    buf[CHECKSUM].copy_from_slice(&checksum.to_le_bytes());
+    Ok(buf)
+}
+
+/// Decodes a datagram, after checking that its header is valid, and
+/// decompresses its packet within the [Limits] for a datagram of any size.
+pub fn decode(datagram: &[u8]) -> Result<(Header, Packet)> {
+    let mut buf = datagram;
+    let header = Header::decode(&mut buf)?;
+
+    if header.magic != MAGIC {
+        let err = Error::new(ErrorKind::Malformed("not a udp-mud datagram"));
+        return Err(err.in_field("Header", "magic"));
+    }
+
+    if header.version != VERSION {
+        let err = Error::new(ErrorKind::Malformed("unsupported protocol version"));
+        return Err(err.in_field("Header", "version"));
+    }
+
+    // fuzzers can't be expected to fix up the checksum of every input
+    if !cfg!(fuzzing) && header.checksum != crc32fast::hash(&datagram[CHECKSUM.end..]) {
+        let err = Error::new(ErrorKind::Malformed("checksum mismatch"));
+        return Err(err.in_field("Header", "checksum"));
+    }
+
+    let body;
+    if header.flags.compressed {
+        body = decompress(buf, &Limits::NETWORK)?;
+        buf = body.as_slice();
+    }
+
+    let packet = Packet::decode(&mut buf)?;
+    if !buf.is_empty() {
+        let err = Error::new(ErrorKind::Malformed("trailing bytes after packet"));
+        return Err(err.in_type("Packet"));
+    }
+
+    Ok((header, packet))
+}
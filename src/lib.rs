@@ -0,0 +1,906 @@
+use ::serde::{Deserialize, Serialize};
+use capture::{CaptureReader, CaptureWriter, Direction, Record};
+use clap::Parser;
+use crossbeam_channel::{Receiver, Sender};
+use cursive::Cursive;
+use datagram::PeerId;
+use protocol::*;
+#[cfg(test)]
+use protocol_derive::Arbitrary;
+use protocol_derive::{Decode, Describe, Encode};
+use sim::SplitMix64;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::BufRead;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use transport::{FallbackTransport, Transport};
+
+pub mod capture;
+pub mod datagram;
+pub mod pronouns;
+pub mod sim;
+pub mod transport;
+mod tui;
+
+#[derive(Parser, Debug)]
+#[clap(
+    author = "Marceline Cramer",
+    about = "Experimental distributed UDP chat."
+)]
+pub struct Args {
+    /// Name you appear as to other peers.
+    #[clap(short, long)]
+    pub username: String,
+
+    /// Address to bind to. Repeat to bind several, such as one IPv4 and one
+    /// IPv6 address, although `[::]` alone usually accepts both.
+    #[clap(short, long = "bind-addr", required = true)]
+    pub bind_addrs: Vec<SocketAddr>,
+
+    /// Other address to initiate connection with.
+    #[clap(short, long)]
+    pub connect: Option<SocketAddr>,
+
+    /// Reach new peers over TCP straight away, for networks that filter
+    /// UDP. Without this, peers that don't reply over UDP within a second
+    /// are tried over TCP anyway. Nodes accept TCP on the same ports as UDP
+    /// where they can, so peers don't need this too.
+    #[clap(long)]
+    pub tcp: bool,
+
+    /// File to capture every datagram sent and received to.
+    #[clap(long)]
+    pub capture: Option<PathBuf>,
+
+    /// Capture to feed the received datagrams of into this node, instead of
+    /// running the TUI.
+    #[clap(long)]
+    pub replay: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub enum Packet {
+    Ping,
+    Pong,
+    RequestUserInfo,
+    RequestRoomInfo(String),
+    RequestRoomList,
+    UserInfo(UserInfo),
+    RoomInfo(RoomInfo),
+    RoomList(RoomList),
+    Message(Message),
+    /// Asks the sender of a datagram from a new address to echo `nonce`
+    /// back from there, before its peer is moved to that address.
+    PathChallenge(u64),
+    /// Answers a [Packet::PathChallenge].
+    PathResponse(u64),
+}
+
+#[derive(Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[protocol(extensible)]
+pub struct UserInfo {
+    pub id: String,
+    pub username: String,
+    pub about: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+#[protocol(extensible)]
+pub struct RoomInfo {
+    pub id: String,
+    pub title: String,
+    pub short_about: String,
+    pub long_about: String,
+}
+
+#[derive(Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct RoomList {
+    pub room_ids: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
+pub struct Message {
+    pub sender: String,
+    pub contents: String,
+}
+
+/// The most datagrams handled by one call to `App::poll`, so that a flood of
+/// them can't keep it from ever returning.
+const MAX_DATAGRAMS_PER_POLL: usize = 64;
+
+/// The most peers a node keeps track of. Beyond this, the one heard from
+/// least recently is forgotten, so that datagrams claiming to be from ever
+/// more peers can't use up memory.
+const MAX_PEERS: usize = 256;
+
+/// A seed that no other node is likely to have, for [App::with_transport].
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    // std's hasher is randomly keyed for each process
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+pub struct Room {
+    pub info: RoomInfo,
+}
+
+/// What a node knows about a peer it has heard from.
+struct Peer {
+    /// Where the peer was last heard from.
+    addr: SocketAddr,
+    /// The newest sequence number heard from the peer.
+    sequence: u32,
+    /// Whether the peer can decompress packets, as told by its last header.
+    accepts_compressed: bool,
+    /// When the peer was last heard from, counted in datagrams received.
+    heard: u64,
+    /// A new address the peer was heard from, and the nonce sent there in a
+    /// [Packet::PathChallenge]. The peer only moves once the nonce is echoed
+    /// back from that address, so that nobody can redirect its traffic to an
+    /// address they merely wrote into a datagram. This does not stop anyone
+    /// who can answer from the new address while claiming the peer's ID.
+    challenge: Option<(SocketAddr, u64)>,
+}
+
+pub struct App {
+    args: Args,
+    transport: Box<dyn Transport>,
+    cursive: Cursive,
+    owned_rooms: HashMap<String, Room>,
+    remote_rooms: HashMap<String, Room>,
+    message_sender: Sender<String>,
+    message_receiver: Receiver<String>,
+    /// Identifies this node to its peers, whatever address it sends from.
+    id: PeerId,
+    /// The peers heard from most recently, up to [MAX_PEERS], by identity
+    /// rather than address so that they can roam.
+    peers: HashMap<PeerId, Peer>,
+    /// How many datagrams have been received, to tell when peers were last
+    /// heard from.
+    received: u64,
+    /// Draws the nonces of path challenges.
+    rng: SplitMix64,
+    /// The sequence number of the next datagram sent.
+    next_sequence: u32,
+    capture: Option<CaptureWriter<File>>,
+    // TODO connection management
+    other: Option<PeerId>,
+}
+
+impl App {
+    pub fn new(args: Args) -> Self {
+        let transport = FallbackTransport::bind(&args.bind_addrs, args.tcp).unwrap();
+        Self::with_transport(args, random_seed(), Box::new(transport))
+    }
+
+    /// Starts a node that talks to its peers over `transport` instead of
+    /// sockets bound to [Args::bind_addrs], and draws its [PeerId] and the
+    /// nonces it challenges peers with from `seed`.
+    pub fn with_transport(args: Args, seed: u64, transport: Box<dyn Transport>) -> Self {
+        let capture = args.capture.as_ref().map(|path| {
+            let file = File::create(path).unwrap();
+            CaptureWriter::new(file).unwrap()
+        });
+
+        let (message_sender, message_receiver) = crossbeam_channel::unbounded();
+
+        let cursive = tui::make_cursive(message_sender.to_owned());
+        let mut rng = SplitMix64(seed);
+
+        let mut app = Self {
+            args,
+            transport,
+            cursive,
+            owned_rooms: Default::default(),
+            remote_rooms: Default::default(),
+            message_sender,
+            message_receiver,
+            id: PeerId(rng.next()),
+            peers: Default::default(),
+            received: 0,
+            rng,
+            next_sequence: 0,
+            capture,
+            other: None,
+        };
+        app.startup();
+        app
+    }
+
+    pub fn startup(&mut self) {
+        if let Some(connect) = self.args.connect {
+            self.send(connect, &Packet::Ping);
+        }
+
+        let room = Room {
+            info: RoomInfo {
+                id: format!("{}_owned_room", self.args.username),
+                title: format!("{}'s Bombass Owned Room", self.args.username),
+                short_about: "An automatically-created room for testing.".into(),
+                long_about: "".into(),
+            },
+        };
+
+        self.owned_rooms.insert(room.info.id.clone(), room);
+    }
+
+    pub fn run(mut self) {
+        let mut siv = tui::make_cursive(self.message_sender.to_owned());
+        let siv_backend = cursive::backends::try_default().unwrap();
+        let mut siv_runner = siv.runner(siv_backend);
+        siv_runner.refresh();
+
+        while siv_runner.is_running() {
+            siv_runner.step();
+
+            for message in self.poll() {
+                tui::add_message(&mut siv_runner, &message);
+                siv_runner.refresh(); // TODO better refresh management
+            }
+        }
+    }
+
+    /// Handles the datagrams waiting on the transport, up to
+    /// `MAX_DATAGRAMS_PER_POLL`, and sends any queued messages, returning the
+    /// messages received.
+    pub fn poll(&mut self) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let mut buf = [0u8; MAX_DATAGRAM_LEN];
+
+        for _ in 0..MAX_DATAGRAMS_PER_POLL {
+            match self.transport.try_recv_from(&mut buf) {
+                Ok(Some((len, from))) => messages.extend(self.on_datagram(from, &buf[..len])),
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to receive datagram: {}", e);
+                    break;
+                }
+            }
+        }
+
+        let other = self.other.and_then(|id| self.peers.get(&id));
+        if let Some(other) = other.map(|peer| peer.addr) {
+            while let Ok(message) = self.message_receiver.try_recv() {
+                eprintln!("sending message: {}", message);
+                let message = Message {
+                    sender: self.args.username.clone(),
+                    contents: message,
+                };
+                self.send(other, &Packet::Message(message));
+            }
+        }
+
+        messages
+    }
+
+    /// Queues a chat message to send once a peer is connected, as if it had
+    /// been typed into the TUI.
+    pub fn send_message(&self, contents: String) {
+        self.message_sender.send(contents).unwrap();
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    pub fn remote_rooms(&self) -> &HashMap<String, Room> {
+        &self.remote_rooms
+    }
+
+    /// Feeds the datagrams received in a capture into this node, in order,
+    /// returning the messages they contained.
+    ///
+    /// The node still replies to what it's fed, so it should be started on a
+    /// transport that reaches nobody, such as one bound to a fresh
+    /// [sim::Network], rather than contact the capture's peers again.
+    pub fn replay(&mut self, reader: impl BufRead) -> Result<Vec<Message>> {
+        let mut capture = CaptureReader::new(reader)?;
+        let mut messages = Vec::new();
+        while let Some(record) = capture.read()? {
+            if record.direction == Direction::Received {
+                messages.extend(self.on_datagram(record.peer, &record.datagram));
+            }
+        }
+
+        Ok(messages)
+    }
+
+    pub fn on_datagram(&mut self, from: SocketAddr, datagram: &[u8]) -> Option<Message> {
+        self.capture(Direction::Received, from, datagram);
+
+        let (header, packet) = match datagram::decode(datagram) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("malformed packet from {}: {}", from, err);
+                return None;
+            }
+        };
+
+        let heard = self.received;
+        self.received += 1;
+
+        if !self.peers.contains_key(&header.sender) && self.peers.len() >= MAX_PEERS {
+            let oldest = self.peers.iter().min_by_key(|(_, peer)| peer.heard);
+            if let Some(id) = oldest.map(|(&id, _)| id) {
+                self.peers.remove(&id);
+            }
+        }
+
+        let peer = self.peers.entry(header.sender).or_insert(Peer {
+            addr: from,
+            sequence: header.sequence,
+            accepts_compressed: false,
+            heard,
+            challenge: None,
+        });
+
+        peer.heard = heard;
+        let newer = header.sequence.wrapping_sub(peer.sequence) as i32 > 0;
+        let mut challenge = None;
+
+        if let Packet::PathResponse(nonce) = packet {
+            if peer.challenge == Some((from, nonce)) {
+                eprintln!(
+                    "peer {} roamed from {} to {}",
+                    header.sender, peer.addr, from
+                );
+                peer.addr = from;
+                peer.sequence = header.sequence;
+                peer.challenge = None;
+            }
+        } else if peer.addr != from && newer {
+            // only newer datagrams can start a move, so that stragglers sent
+            // from an old address don't move the peer back
+            let nonce = match peer.challenge {
+                Some((addr, nonce)) if addr == from => nonce,
+                _ => self.rng.next(),
+            };
+
+            peer.challenge = Some((from, nonce));
+            challenge = Some(nonce);
+        }
+
+        if peer.addr == from {
+            if newer {
+                peer.sequence = header.sequence;
+            }
+
+            peer.accepts_compressed = header.flags.accepts_compressed;
+        }
+
+        if let Some(nonce) = challenge {
+            self.send(from, &Packet::PathChallenge(nonce));
+        }
+
+        // TODO proper connection management
+        self.other = Some(header.sender);
+
+        self.on_packet(from, packet)
+    }
+
+    pub fn on_packet(&mut self, from: SocketAddr, packet: Packet) -> Option<Message> {
+        match packet {
+            Packet::Ping => self.send(from, &Packet::Pong),
+            Packet::PathChallenge(nonce) => self.send(from, &Packet::PathResponse(nonce)),
+            Packet::PathResponse(_) => {}
+            Packet::Pong => self.send(from, &Packet::RequestRoomList),
+            Packet::RequestRoomList => {
+                let room_list = self.build_room_list();
+                self.send(from, &Packet::RoomList(room_list));
+            }
+            Packet::RoomList(room_list) => {
+                for room_id in room_list.room_ids.into_iter() {
+                    self.send(from, &Packet::RequestRoomInfo(room_id));
+                }
+            }
+            Packet::RequestRoomInfo(room_id) => {
+                if let Some(room) = self.owned_rooms.get(&room_id) {
+                    let info = room.info.clone();
+                    self.send(from, &Packet::RoomInfo(info));
+                } else {
+                    eprintln!("Unrecognized room info request for {}", room_id);
+                }
+            }
+            Packet::RoomInfo(info) => {
+                eprintln!("Received room info: {:#?}", info);
+                self.remote_rooms.insert(info.id.clone(), Room { info });
+            }
+            Packet::Message(message) => return Some(message),
+            packet => eprintln!("unimplemented packet handler for {:?}", packet),
+        }
+
+        None
+    }
+
+    pub fn build_room_list(&self) -> RoomList {
+        let room_ids: Vec<_> = self
+            .owned_rooms
+            .iter()
+            .map(|(id, _room)| id.to_owned())
+            .collect();
+        RoomList { room_ids }
+    }
+
+    pub fn send_packet(&mut self, addr: SocketAddr, packet: &Packet) -> std::io::Result<()> {
+        let compressed = self
+            .peers
+            .values()
+            .any(|peer| peer.addr == addr && peer.accepts_compressed);
+        let buf = datagram::encode(packet, self.id, self.next_sequence, compressed)?;
+
+        // TODO fragmentation
+        if buf.len() > MAX_DATAGRAM_LEN {
+            let msg = format!("{} byte packet does not fit in a datagram", buf.len());
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg));
+        }
+
+        self.transport.send_to(&buf, addr)?;
+        self.capture(Direction::Sent, addr, &buf);
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Sends `packet` to `addr`, logging rather than failing if it can't be
+    /// sent, since peers can go away at any time. Peers whose connection has
+    /// closed are forgotten until they reach this node again.
+    fn send(&mut self, addr: SocketAddr, packet: &Packet) {
+        if let Err(err) = self.send_packet(addr, packet) {
+            eprintln!("failed to send packet to {}: {}", addr, err);
+            if err.kind() == std::io::ErrorKind::NotConnected {
+                self.peers.retain(|_, peer| peer.addr != addr);
+            }
+        }
+    }
+
+    fn capture(&mut self, direction: Direction, peer: SocketAddr, datagram: &[u8]) {
+        if let Some(capture) = self.capture.as_mut() {
+            let record = Record::now(direction, peer, datagram);
+            if let Err(err) = capture.write(&record) {
+                eprintln!("failed to capture datagram: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pronouns::Pronouns;
+    use protocol::testing::{check_roundtrip, check_truncated, encode};
+    use sim::{Event, Network};
+    use std::path::Path;
+    use std::time::Duration;
+    use transport::TcpTransport;
+
+    /// Compares `contents` against a checked-in file, or overwrites the file
+    /// when the environment variable `update` is set.
+    fn check_golden(path: &Path, contents: &[u8], update: &str) {
+        if std::env::var_os(update).is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        } else {
+            let expected = std::fs::read(path).unwrap_or_default();
+            assert!(
+                expected == contents,
+                "{} is out of date; rerun with {}=1 if the change is intended",
+                path.display(),
+                update
+            );
+        }
+    }
+
+    /// Checks the wire format of every packet against the schema files in
+    /// `schema/`, or updates them when `UPDATE_SCHEMA` is set.
+    #[test]
+    fn schema() {
+        let mut schema = Schema::of::<datagram::Header>();
+        Packet::define(&mut schema);
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schema");
+        let files = [
+            ("packets.json", schema.to_json()),
+            ("packets.md", schema.to_markdown()),
+        ];
+
+        for (name, contents) in files {
+            check_golden(&dir.join(name), contents.as_bytes(), "UPDATE_SCHEMA");
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        check_roundtrip::<Packet>();
+        check_roundtrip::<UserInfo>();
+        check_roundtrip::<RoomInfo>();
+        check_roundtrip::<RoomList>();
+        check_roundtrip::<Message>();
+        check_roundtrip::<Pronouns>();
+    }
+
+    #[test]
+    fn truncated() {
+        check_truncated::<Packet>();
+        check_truncated::<UserInfo>();
+        check_truncated::<RoomInfo>();
+        check_truncated::<RoomList>();
+        check_truncated::<Message>();
+        check_truncated::<Pronouns>();
+    }
+
+    #[test]
+    fn json() {
+        let packets = [
+            Packet::Ping,
+            Packet::RequestRoomInfo("lobby".to_string()),
+            Packet::UserInfo(UserInfo {
+                id: "7".to_string(),
+                username: "wanderer".to_string(),
+                about: "Just passing through.".to_string(),
+            }),
+            Packet::RoomList(RoomList {
+                room_ids: vec!["lobby".to_string(), "attic".to_string()],
+            }),
+        ];
+
+        for packet in packets {
+            let json = serde_json::to_string(&packet).unwrap();
+            assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn datagram() {
+        let info = RoomInfo {
+            id: "lobby".to_string(),
+            title: "Lobby".to_string(),
+            short_about: "Where everyone starts.".to_string(),
+            long_about: "Say hello! ".repeat(64),
+        };
+
+        let packet = Packet::RoomInfo(info);
+        let plain = datagram::encode(&packet, PeerId(7), 1, false).unwrap();
+        let compressed = datagram::encode(&packet, PeerId(7), 2, true).unwrap();
+        assert!(compressed.len() < plain.len() / 4);
+
+        for buf in [plain, compressed] {
+            let (header, decoded) = datagram::decode(&buf).unwrap();
+            assert!(header.flags.accepts_compressed);
+            assert_eq!(decoded, packet);
+        }
+
+        // too short to be worth compressing
+        let buf = datagram::encode(&Packet::Ping, PeerId(7), 3, true).unwrap();
+        let (header, _) = datagram::decode(&buf).unwrap();
+        assert!(!header.flags.compressed);
+        assert_eq!(header.sequence, 3);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let message = Message {
+            sender: "bob".to_string(),
+            contents: "hello!".to_string(),
+        };
+
+        let buf = datagram::encode(&Packet::Message(message), PeerId(7), 0, false).unwrap();
+        let check = |buf: &[u8], expected: &str| {
+            let err = datagram::decode(buf).unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        };
+
+        let mut corrupted = buf.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        check(&corrupted, "Header.checksum: checksum mismatch");
+
+        let mut stray = buf.clone();
+        stray[..4].copy_from_slice(b"GET ");
+        check(&stray, "Header.magic: not a udp-mud datagram");
+
+        let mut trailing = buf.clone();
+        trailing.push(0);
+        let checksum = crc32fast::hash(&trailing[9..]);
+        trailing[5..9].copy_from_slice(&checksum.to_le_bytes());
+        check(&trailing, "Packet: trailing bytes after packet");
+
+        let mut future = buf;
+        future[4] += 1;
+        check(&future, "Header.version: unsupported protocol version");
+
+        // a bare packet, as sent before datagrams had headers
+        check(
+            &encode(&Packet::Ping),
+            "Header.magic: unexpected end of input",
+        );
+    }
+
+    #[test]
+    fn replay() {
+        let from = SocketAddr::from(([192, 0, 2, 1], 7000));
+
+        let message = || Message {
+            sender: "bob".to_string(),
+            contents: "hello!".to_string(),
+        };
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for packet in [Packet::Ping, Packet::Message(message())] {
+            let datagram = datagram::encode(&packet, PeerId(7), 0, false).unwrap();
+            let record = Record::now(Direction::Received, from, &datagram);
+            writer.write(&record).unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("udp-mud-replay-{}", std::process::id()));
+        let network = Network::new(0);
+        let transport = network.bind();
+        let args = Args {
+            username: "alice".to_string(),
+            bind_addrs: vec![transport.local_addr().unwrap()],
+            connect: None,
+            tcp: false,
+            capture: Some(path.clone()),
+            replay: None,
+        };
+
+        let mut app = App::with_transport(args, network.seed(), Box::new(transport));
+
+        let capture = writer.into_inner();
+        let messages = app.replay(capture.as_slice()).unwrap();
+        assert_eq!(messages, [message()]);
+
+        // the replay is captured again along with the reply to the ping
+        let file = std::io::BufReader::new(File::open(&path).unwrap());
+        let mut reader = CaptureReader::new(file).unwrap();
+        let mut records = Vec::new();
+        while let Some(record) = reader.read().unwrap() {
+            records.push(record);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+
+        let packets: Vec<_> = records
+            .iter()
+            .map(|record| {
+                let (_, packet) = datagram::decode(&record.datagram).unwrap();
+                (record.direction, packet)
+            })
+            .collect();
+
+        assert_eq!(
+            packets,
+            [
+                (Direction::Received, Packet::Ping),
+                (Direction::Sent, Packet::Pong),
+                (Direction::Received, Packet::Message(message())),
+            ]
+        );
+
+        // the pong went nowhere
+        network.advance(Duration::from_secs(1));
+        let log = network.log();
+        assert!(matches!(log[..], [Event::Sent { .. }, Event::Dropped { to, .. }] if to == from));
+    }
+
+    fn tcp_args(username: &str, connect: Option<SocketAddr>) -> Args {
+        Args {
+            username: username.to_string(),
+            bind_addrs: vec![SocketAddr::from(([127, 0, 0, 1], 0))],
+            connect,
+            tcp: true,
+            capture: None,
+            replay: None,
+        }
+    }
+
+    #[test]
+    fn tcp_handshake() {
+        let mut bob = App::new(tcp_args("bob", None));
+        let mut alice = App::new(tcp_args("alice", Some(bob.local_addr().unwrap())));
+        alice.send_message("hello over tcp".to_string());
+
+        let mut received = Vec::new();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while (received.is_empty() || alice.remote_rooms().is_empty())
+            && std::time::Instant::now() < deadline
+        {
+            alice.poll();
+            received.extend(bob.poll());
+        }
+
+        assert_eq!(contents(&received), ["hello over tcp"]);
+        let rooms: Vec<_> = alice.remote_rooms().keys().collect();
+        assert_eq!(rooms, ["bob_owned_room"]);
+    }
+
+    #[test]
+    fn tcp_fallback() {
+        let mut args = tcp_args("bob", None);
+        args.tcp = false;
+        let mut bob = App::new(args);
+        let mut alice = App::new(tcp_args("alice", Some(bob.local_addr().unwrap())));
+        alice.send_message("hello from behind a firewall".to_string());
+
+        // bob replies over the connection alice opened, since UDP would go
+        // to a port she isn't listening on
+        let mut received = Vec::new();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while (received.is_empty() || alice.remote_rooms().is_empty())
+            && std::time::Instant::now() < deadline
+        {
+            alice.poll();
+            received.extend(bob.poll());
+        }
+
+        assert_eq!(contents(&received), ["hello from behind a firewall"]);
+        let rooms: Vec<_> = alice.remote_rooms().keys().collect();
+        assert_eq!(rooms, ["bob_owned_room"]);
+    }
+
+    #[test]
+    fn tcp_after_udp_timeout() {
+        // bob's network lets nothing but TCP through
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let transport = TcpTransport::bind(&[localhost]).unwrap();
+        let mut bob = App::with_transport(tcp_args("bob", None), 0, Box::new(transport));
+
+        let mut args = tcp_args("alice", Some(bob.local_addr().unwrap()));
+        args.tcp = false;
+        let mut alice = App::new(args);
+        alice.send_message("is this thing on?".to_string());
+
+        // alice gives up on UDP and sends her ping again over TCP
+        let mut received = Vec::new();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while (received.is_empty() || alice.remote_rooms().is_empty())
+            && std::time::Instant::now() < deadline
+        {
+            alice.poll();
+            received.extend(bob.poll());
+        }
+
+        assert_eq!(contents(&received), ["is this thing on?"]);
+        let rooms: Vec<_> = alice.remote_rooms().keys().collect();
+        assert_eq!(rooms, ["bob_owned_room"]);
+    }
+
+    #[test]
+    fn tcp_peer_exit() {
+        let mut bob = App::new(tcp_args("bob", None));
+        let mut alice = App::new(tcp_args("alice", Some(bob.local_addr().unwrap())));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while alice.remote_rooms().is_empty() && std::time::Instant::now() < deadline {
+            alice.poll();
+            bob.poll();
+        }
+
+        assert!(!alice.remote_rooms().is_empty());
+
+        // bob can only reach alice over the connection she opened, so once
+        // she's gone he forgets her instead of redialling her
+        drop(alice);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !bob.peers.is_empty() && std::time::Instant::now() < deadline {
+            bob.send_message("anyone there?".to_string());
+            bob.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(bob.peers.is_empty());
+
+        // nobody listening doesn't hold up or take down a node
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+
+        let started = std::time::Instant::now();
+        let mut carol = App::new(tcp_args("carol", Some(closed_addr)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        for _ in 0..100 {
+            carol.poll();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub(crate) fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.contents.as_str()).collect()
+    }
+
+    /// Checks the seed inputs in `fuzz/corpus/` against the packets two
+    /// peers exchange during a handshake, or updates them when
+    /// `UPDATE_FUZZ_CORPUS` is set.
+    #[test]
+    fn fuzz_corpus() {
+        let pronouns = pronouns::make_presets();
+
+        let user_info = UserInfo {
+            id: "alice".to_string(),
+            username: "alice".to_string(),
+            about: "Just here to chat.".to_string(),
+        };
+
+        let room_info = RoomInfo {
+            id: "alice_owned_room".to_string(),
+            title: "alice's Bombass Owned Room".to_string(),
+            short_about: "An automatically-created room for testing.".to_string(),
+            long_about: "".to_string(),
+        };
+
+        let room_list = RoomList {
+            room_ids: vec![room_info.id.clone()],
+        };
+
+        let message = Message {
+            sender: "bob".to_string(),
+            contents: "hello!".to_string(),
+        };
+
+        let mut seeds = vec![
+            ("user_info", "alice".to_string(), encode(&user_info)),
+            (
+                "room_info",
+                "alice_owned_room".to_string(),
+                encode(&room_info),
+            ),
+            ("room_list", "alice".to_string(), encode(&room_list)),
+            ("message", "hello".to_string(), encode(&message)),
+            ("string", "room_id".to_string(), encode(&room_info.id)),
+        ];
+
+        for (i, preset) in pronouns.iter().enumerate() {
+            seeds.push(("pronouns", format!("preset_{}", i), encode(preset)));
+        }
+
+        let packets = [
+            ("ping", Packet::Ping),
+            ("pong", Packet::Pong),
+            ("request_user_info", Packet::RequestUserInfo),
+            ("user_info", Packet::UserInfo(user_info)),
+            ("request_room_list", Packet::RequestRoomList),
+            ("room_list", Packet::RoomList(room_list)),
+            (
+                "request_room_info",
+                Packet::RequestRoomInfo(room_info.id.clone()),
+            ),
+            ("room_info", Packet::RoomInfo(room_info)),
+            ("message", Packet::Message(message)),
+        ];
+
+        for (name, packet) in packets {
+            let buf = encode(&packet);
+            seeds.push(("packet", name.to_string(), buf.clone()));
+            seeds.push(("on_packet", name.to_string(), buf));
+
+            let buf = datagram::encode(&packet, PeerId(7), 0, true).unwrap();
+            seeds.push(("datagram", name.to_string(), buf));
+        }
+
+        let long_info = RoomInfo {
+            id: "alice_owned_room".to_string(),
+            title: "alice's Bombass Owned Room".to_string(),
+            short_about: "An automatically-created room for testing.".to_string(),
+            long_about: "A room for testing. ".repeat(32),
+        };
+
+        let buf = datagram::encode(&Packet::RoomInfo(long_info), PeerId(7), 0, true).unwrap();
+        seeds.push(("datagram", "room_info_compressed".to_string(), buf));
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus");
+        for (target, name, contents) in seeds {
+            let path = dir.join(target).join(name);
+            check_golden(&path, &contents, "UPDATE_FUZZ_CORPUS");
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use protocol_derive::{Decode, Encode};
+#[cfg(test)]
+use protocol_derive::Arbitrary;
+use protocol_derive::{Decode, Describe, Encode};
 use serde::{Deserialize, Serialize};
 
 pub const EXAMPLE_USAGE: &str = "{S} went to the park.
@@ -47,7 +49,8 @@ pub fn make_presets() -> Vec<Pronouns> {
         .collect()
 }
 
-#[derive(Clone, Debug, Decode, Encode)]
+#[derive(Clone, Debug, PartialEq, Decode, Describe, Deserialize, Encode, Serialize)]
+#[cfg_attr(test, derive(Arbitrary))]
 pub struct Pronouns {
     pub case_sensitive: bool,
     pub plural: bool,
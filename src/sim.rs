@@ -0,0 +1,600 @@
+//! A deterministic in-memory network, for testing nodes against each other
+//! without real sockets.
+//!
+//! Every datagram sent on a [Network] is delayed, dropped, duplicated or
+//! reordered according to its [Conditions], using a random number generator
+//! seeded up front. Time only passes when [Network::advance] is called, so
+//! the same seed and the same calls always deliver the same datagrams in the
+//! same order.
+
+use crate::transport::Transport;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// How badly a [Network] treats the datagrams sent on it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conditions {
+    /// Chance from 0 to 1 that a datagram is dropped.
+    pub loss: f64,
+
+    /// Chance from 0 to 1 that a datagram is delivered twice.
+    pub duplication: f64,
+
+    /// The least time a datagram takes to be delivered.
+    pub latency: Duration,
+
+    /// Up to this much is added to the latency of each datagram at random,
+    /// so that datagrams sent close together may arrive out of order.
+    pub jitter: Duration,
+}
+
+impl Conditions {
+    /// Every datagram is delivered exactly once, in order, after 1ms.
+    pub const PERFECT: Self = Self {
+        loss: 0.0,
+        duplication: 0.0,
+        latency: Duration::from_millis(1),
+        jitter: Duration::ZERO,
+    };
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self::PERFECT
+    }
+}
+
+/// Something that happened to a datagram, as logged by [Network::log].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Sent {
+        at: Duration,
+        from: SocketAddr,
+        to: SocketAddr,
+        datagram: Vec<u8>,
+    },
+    Dropped {
+        at: Duration,
+        from: SocketAddr,
+        to: SocketAddr,
+    },
+    Delivered {
+        at: Duration,
+        from: SocketAddr,
+        to: SocketAddr,
+        datagram: Vec<u8>,
+    },
+}
+
+/// A simulated network that [SimTransport]s send datagrams over.
+///
+/// Cloning a network gives another handle to the same one.
+#[derive(Clone)]
+pub struct Network {
+    state: Rc<RefCell<State>>,
+}
+
+struct State {
+    rng: SplitMix64,
+    conditions: Conditions,
+    now: Duration,
+    /// Datagrams on their way, soonest first. The counter keeps datagrams
+    /// due at the same time in the order they were sent.
+    in_flight: BinaryHeap<Reverse<(Duration, u64, InFlight)>>,
+    sent: u64,
+    /// The current address of each node, indexed by [SimTransport::node].
+    nodes: Vec<SocketAddr>,
+    /// The node at each address that is bound.
+    bound: HashMap<SocketAddr, usize>,
+    /// Datagrams delivered to each node but not yet received.
+    inboxes: Vec<VecDeque<(SocketAddr, Vec<u8>)>>,
+    /// How many addresses have been handed out.
+    hosts: u32,
+    /// Pairs of addresses that can't reach each other, in both directions.
+    partitions: HashSet<(SocketAddr, SocketAddr)>,
+    log: Vec<Event>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    from: SocketAddr,
+    to: SocketAddr,
+    datagram: Vec<u8>,
+}
+
+impl Network {
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            rng: SplitMix64(seed),
+            conditions: Conditions::default(),
+            now: Duration::ZERO,
+            in_flight: BinaryHeap::new(),
+            sent: 0,
+            nodes: Vec::new(),
+            bound: HashMap::new(),
+            inboxes: Vec::new(),
+            hosts: 0,
+            partitions: HashSet::new(),
+            log: Vec::new(),
+        };
+
+        Self {
+            state: Rc::new(RefCell::new(state)),
+        }
+    }
+
+    /// Applies `conditions` to every datagram sent from now on.
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.state.borrow_mut().conditions = conditions;
+    }
+
+    /// Adds a node to the network at the next free address, starting from
+    /// `10.0.0.1:7000`.
+    pub fn bind(&self) -> SimTransport {
+        let mut state = self.state.borrow_mut();
+        let node = state.nodes.len();
+        let addr = state.next_addr();
+        state.nodes.push(addr);
+        state.bound.insert(addr, node);
+        state.inboxes.push(VecDeque::new());
+
+        SimTransport {
+            node,
+            network: self.clone(),
+        }
+    }
+
+    /// Draws a seed for a node from the network's random number generator,
+    /// rather than [crate::random_seed], so that runs can be repeated.
+    pub fn seed(&self) -> u64 {
+        self.state.borrow_mut().rng.next()
+    }
+
+    /// Moves the node at `addr` to the next free address, as if it had
+    /// switched networks, and returns its new address. Datagrams still on
+    /// their way to the old address are lost.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no node is at `addr`.
+    pub fn roam(&self, addr: SocketAddr) -> SocketAddr {
+        let mut state = self.state.borrow_mut();
+        let node = state.bound.remove(&addr).expect("no node at address");
+        let addr = state.next_addr();
+        state.nodes[node] = addr;
+        state.bound.insert(addr, node);
+        addr
+    }
+
+    /// Stops datagrams between every address in `a` and every address in
+    /// `b`, in both directions, including those already on their way.
+    pub fn partition(&self, a: &[SocketAddr], b: &[SocketAddr]) {
+        let mut state = self.state.borrow_mut();
+        for &a in a {
+            for &b in b {
+                state.partitions.insert((a, b));
+                state.partitions.insert((b, a));
+            }
+        }
+    }
+
+    /// Removes every partition.
+    pub fn heal(&self) {
+        self.state.borrow_mut().partitions.clear();
+    }
+
+    /// How much simulated time has passed.
+    pub fn now(&self) -> Duration {
+        self.state.borrow().now
+    }
+
+    /// Whether no datagrams are on their way or waiting to be received.
+    pub fn is_idle(&self) -> bool {
+        let state = self.state.borrow();
+        state.in_flight.is_empty() && state.inboxes.iter().all(VecDeque::is_empty)
+    }
+
+    /// Moves time forward by `by`, delivering every datagram due by then.
+    pub fn advance(&self, by: Duration) {
+        let mut state = self.state.borrow_mut();
+        let until = state.now + by;
+
+        while let Some(Reverse((at, _, _))) = state.in_flight.peek() {
+            if *at > until {
+                break;
+            }
+
+            let Reverse((at, _, datagram)) = state.in_flight.pop().unwrap();
+            state.now = at;
+            state.deliver(datagram);
+        }
+
+        state.now = until;
+    }
+
+    /// Everything that has happened to datagrams so far.
+    pub fn log(&self) -> Vec<Event> {
+        self.state.borrow().log.clone()
+    }
+}
+
+impl State {
+    fn next_addr(&mut self) -> SocketAddr {
+        self.hosts += 1;
+        let ip = Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + self.hosts);
+        SocketAddr::from((ip, 7000))
+    }
+
+    fn is_partitioned(&self, from: SocketAddr, to: SocketAddr) -> bool {
+        self.partitions.contains(&(from, to))
+    }
+
+    fn send(&mut self, from: SocketAddr, to: SocketAddr, datagram: &[u8]) {
+        let at = self.now;
+        self.log.push(Event::Sent {
+            at,
+            from,
+            to,
+            datagram: datagram.to_vec(),
+        });
+
+        if self.is_partitioned(from, to) || self.rng.chance(self.conditions.loss) {
+            self.log.push(Event::Dropped { at, from, to });
+            return;
+        }
+
+        let copies = if self.rng.chance(self.conditions.duplication) {
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            let jitter = self.conditions.jitter.as_nanos() as u64;
+            let jitter = Duration::from_nanos(self.rng.below(jitter + 1));
+            let due = self.now + self.conditions.latency + jitter;
+
+            let datagram = InFlight {
+                from,
+                to,
+                datagram: datagram.to_vec(),
+            };
+
+            self.sent += 1;
+            self.in_flight.push(Reverse((due, self.sent, datagram)));
+        }
+    }
+
+    fn deliver(&mut self, datagram: InFlight) {
+        let InFlight { from, to, datagram } = datagram;
+        let at = self.now;
+
+        // addresses nobody is bound to, like partitioned ones, swallow
+        // datagrams just as the real network would
+        let node = match self.bound.get(&to) {
+            Some(&node) if !self.is_partitioned(from, to) => node,
+            _ => {
+                self.log.push(Event::Dropped { at, from, to });
+                return;
+            }
+        };
+
+        self.log.push(Event::Delivered {
+            at,
+            from,
+            to,
+            datagram: datagram.clone(),
+        });
+
+        self.inboxes[node].push_back((from, datagram));
+    }
+}
+
+/// A node's connection to a [Network].
+pub struct SimTransport {
+    node: usize,
+    network: Network,
+}
+
+impl Transport for SimTransport {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.network.state.borrow().nodes[self.node])
+    }
+
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()> {
+        let mut state = self.network.state.borrow_mut();
+        let from = state.nodes[self.node];
+        state.send(from, to, datagram);
+        Ok(())
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        let mut state = self.network.state.borrow_mut();
+        let inbox = &mut state.inboxes[self.node];
+        let (from, datagram) = match inbox.pop_front() {
+            Some(received) => received,
+            None => return Ok(None),
+        };
+
+        if datagram.len() > buf.len() {
+            let msg = format!(
+                "{} byte datagram does not fit in the buffer",
+                datagram.len()
+            );
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+
+        buf[..datagram.len()].copy_from_slice(&datagram);
+        Ok(Some((datagram.len(), from)))
+    }
+}
+
+/// A small, fast and well-distributed random number generator, so that
+/// seeded runs don't depend on any particular version of a crate.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, or 0 if `n` is 0.
+    fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            0
+        } else {
+            self.next() % n
+        }
+    }
+
+    /// True with probability `p`.
+    fn chance(&mut self, p: f64) -> bool {
+        // always draw, so that changing `p` doesn't shift every later draw
+        let draw = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        draw < p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datagram::{self, PeerId};
+    use crate::tests::contents;
+    use crate::{App, Args, Message, Packet, MAX_DATAGRAMS_PER_POLL, MAX_PEERS};
+
+    /// Starts a node on `network` that connects to `connect` if it's set.
+    fn sim_node(network: &Network, username: &str, connect: Option<SocketAddr>) -> App {
+        let transport = network.bind();
+        let args = Args {
+            username: username.to_string(),
+            bind_addrs: vec![transport.local_addr().unwrap()],
+            connect,
+            tcp: false,
+            capture: None,
+            replay: None,
+        };
+
+        App::with_transport(args, network.seed(), Box::new(transport))
+    }
+
+    /// Polls every node, a millisecond of simulated time apart, until no
+    /// datagrams are left on the network. Returns what each node received.
+    fn settle(network: &Network, nodes: &mut [&mut App]) -> Vec<Vec<Message>> {
+        let mut received: Vec<_> = nodes.iter().map(|_| Vec::new()).collect();
+
+        for _ in 0..1000 {
+            for (node, received) in nodes.iter_mut().zip(received.iter_mut()) {
+                received.extend(node.poll());
+            }
+
+            if network.is_idle() {
+                break;
+            }
+
+            network.advance(Duration::from_millis(1));
+        }
+
+        received
+    }
+
+    #[test]
+    fn handshake() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let mut alice = sim_node(&network, "alice", Some(bob.local_addr().unwrap()));
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        let rooms: Vec<_> = alice.remote_rooms().keys().collect();
+        assert_eq!(rooms, ["bob_owned_room"]);
+        assert!(bob.remote_rooms().is_empty());
+    }
+
+    #[test]
+    fn messaging() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let mut alice = sim_node(&network, "alice", Some(bob.local_addr().unwrap()));
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        alice.send_message("hi bob".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(received[1].len(), 1);
+        assert_eq!(received[1][0].sender, "alice");
+        assert_eq!(received[1][0].contents, "hi bob");
+
+        bob.send_message("hi alice".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(contents(&received[0]), ["hi alice"]);
+    }
+
+    #[test]
+    fn unreliable() {
+        let network = Network::new(1);
+        network.set_conditions(Conditions {
+            duplication: 0.5,
+            jitter: Duration::from_millis(20),
+            ..Conditions::PERFECT
+        });
+
+        let mut bob = sim_node(&network, "bob", None);
+        let mut alice = sim_node(&network, "alice", Some(bob.local_addr().unwrap()));
+        settle(&network, &mut [&mut alice, &mut bob]);
+        assert!(alice.remote_rooms().contains_key("bob_owned_room"));
+
+        for i in 0..8 {
+            alice.send_message(i.to_string());
+        }
+
+        // every message arrives at least once, though not always in order
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        let mut received = contents(&received[1]);
+        received.sort();
+        received.dedup();
+        assert_eq!(received, ["0", "1", "2", "3", "4", "5", "6", "7"]);
+    }
+
+    #[test]
+    fn partition() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let bob_addr = bob.local_addr().unwrap();
+        let mut alice = sim_node(&network, "alice", Some(bob_addr));
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        network.partition(&[alice.local_addr().unwrap()], &[bob_addr]);
+        alice.send_message("lost".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert!(received[1].is_empty());
+
+        network.heal();
+        alice.send_message("found".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(contents(&received[1]), ["found"]);
+    }
+
+    #[test]
+    fn roaming() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let mut alice = sim_node(&network, "alice", Some(bob.local_addr().unwrap()));
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        let roamed = network.roam(alice.local_addr().unwrap());
+        assert_eq!(alice.local_addr().unwrap(), roamed);
+
+        alice.send_message("moved".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(contents(&received[1]), ["moved"]);
+
+        // bob follows alice to her new address
+        bob.send_message("welcome".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(contents(&received[0]), ["welcome"]);
+    }
+
+    #[test]
+    fn hijack() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let bob_addr = bob.local_addr().unwrap();
+        let mut alice = sim_node(&network, "alice", Some(bob_addr));
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        // mallory claims to be alice, but never answers bob's challenge
+        let mut mallory = network.bind();
+        let buf = datagram::encode(&Packet::Ping, alice.id, u32::MAX / 2, false).unwrap();
+        mallory.send_to(&buf, bob_addr).unwrap();
+        settle(&network, &mut [&mut alice, &mut bob]);
+
+        let mut challenge = [0u8; 64];
+        let (len, _) = mallory.try_recv_from(&mut challenge).unwrap().unwrap();
+        let (_, packet) = datagram::decode(&challenge[..len]).unwrap();
+        assert!(matches!(packet, Packet::PathChallenge(_)));
+
+        bob.send_message("still there?".to_string());
+        let received = settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(contents(&received[0]), ["still there?"]);
+    }
+
+    #[test]
+    fn many_peers() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let bob_addr = bob.local_addr().unwrap();
+        let mut mallory = network.bind();
+
+        for _ in 0..MAX_PEERS + 16 {
+            let buf = datagram::encode(&Packet::Pong, PeerId(network.seed()), 0, false).unwrap();
+            mallory.send_to(&buf, bob_addr).unwrap();
+        }
+
+        settle(&network, &mut [&mut bob]);
+        assert_eq!(bob.peers.len(), MAX_PEERS);
+
+        // the peer heard from most recently is still known
+        let mut alice = sim_node(&network, "alice", Some(bob_addr));
+        settle(&network, &mut [&mut alice, &mut bob]);
+        assert_eq!(bob.peers.len(), MAX_PEERS);
+        assert!(bob.peers.contains_key(&alice.id));
+    }
+
+    #[test]
+    fn flood() {
+        let network = Network::new(0);
+        let mut bob = sim_node(&network, "bob", None);
+        let bob_addr = bob.local_addr().unwrap();
+        let mut mallory = network.bind();
+        let mallory_id = PeerId(network.seed());
+
+        for sequence in 0..100 {
+            let message = Message {
+                sender: "mallory".to_string(),
+                contents: sequence.to_string(),
+            };
+
+            let packet = Packet::Message(message);
+            let buf = datagram::encode(&packet, mallory_id, sequence, false).unwrap();
+            mallory.send_to(&buf, bob_addr).unwrap();
+        }
+
+        // a flood is handled over several polls rather than all at once
+        network.advance(Duration::from_millis(1));
+        assert_eq!(bob.poll().len(), MAX_DATAGRAMS_PER_POLL);
+        assert_eq!(bob.poll().len(), 100 - MAX_DATAGRAMS_PER_POLL);
+    }
+
+    #[test]
+    fn deterministic() {
+        let run = |seed| {
+            let network = Network::new(seed);
+            network.set_conditions(Conditions {
+                loss: 0.2,
+                duplication: 0.2,
+                latency: Duration::from_millis(5),
+                jitter: Duration::from_millis(10),
+            });
+
+            let mut bob = sim_node(&network, "bob", None);
+            let mut alice = sim_node(&network, "alice", Some(bob.local_addr().unwrap()));
+            for i in 0..16 {
+                alice.send_message(i.to_string());
+            }
+
+            settle(&network, &mut [&mut alice, &mut bob]);
+            network.log()
+        };
+
+        // the log holds every datagram in full, peer IDs and all
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+}
@@ -0,0 +1,864 @@
+use crossbeam_channel::{Receiver, TryRecvError};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Moves datagrams between a node and its peers.
+///
+/// [App](crate::App) only talks to the network through this, so that it can
+/// run over real [UdpSocket]s, over TCP where UDP is filtered, and over the
+/// simulated network in [crate::sim].
+pub trait Transport {
+    /// The address peers reach this transport at.
+    fn local_addr(&self) -> Result<SocketAddr>;
+
+    /// Sends one datagram to `to`.
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()>;
+
+    /// Receives one datagram into `buf` if any are waiting, returning its
+    /// length and sender, or `None` without blocking if there are none.
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>>;
+}
+
+/// The socket must be non-blocking. [UdpTransport] takes care of that.
+impl Transport for UdpSocket {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()> {
+        UdpSocket::send_to(self, datagram, to).map(|_| ())
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        match self.recv_from(buf) {
+            Ok(received) => Ok(Some(received)),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// The most peers a [UdpTransport] remembers the socket of. Beyond this, the
+/// route learned longest ago is forgotten, so that datagrams from ever more
+/// addresses can't use up memory. Peers without a route are sent to as if
+/// they had never been heard from.
+const MAX_ROUTES: usize = 1024;
+
+/// Any number of UDP sockets, such as one for IPv4 and one for IPv6, or one
+/// for each of several interfaces.
+///
+/// Replies go out of the socket that the peer was last heard from on, so
+/// they come from the address the peer expects. Peers that haven't been
+/// heard from yet are sent to from the first socket of the same family.
+pub struct UdpTransport {
+    sockets: Vec<UdpSocket>,
+    /// Which socket each peer was last heard from on.
+    routes: HashMap<SocketAddr, usize>,
+    /// The peers in `routes`, in the order they were first heard from.
+    route_order: VecDeque<SocketAddr>,
+    /// The socket to receive from first next time, so that a busy socket
+    /// can't starve the others.
+    next_recv: usize,
+}
+
+impl UdpTransport {
+    /// Binds a non-blocking socket to each of `addrs`.
+    ///
+    /// On most systems a socket bound to `[::]` also receives IPv4, so
+    /// binding both it and `0.0.0.0` to the same port fails.
+    pub fn bind(addrs: &[SocketAddr]) -> Result<Self> {
+        if addrs.is_empty() {
+            let msg = "no addresses to bind to";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+
+        let sockets = addrs
+            .iter()
+            .map(|addr| {
+                let socket = UdpSocket::bind(addr)?;
+                socket.set_nonblocking(true)?;
+                Ok(socket)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            sockets,
+            routes: HashMap::new(),
+            route_order: VecDeque::new(),
+            next_recv: 0,
+        })
+    }
+
+    /// The addresses of every socket, in the order they were bound.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        self.sockets.iter().map(UdpSocket::local_addr).collect()
+    }
+
+    /// Picks the socket to send to `to` from, and the address to send to.
+    ///
+    /// IPv4 peers can be reached from an IPv6 socket, like one bound to
+    /// `[::]`, at their IPv4-mapped address if no IPv4 socket is bound.
+    fn route(&self, to: SocketAddr) -> Result<(&UdpSocket, SocketAddr)> {
+        if let Some(&index) = self.routes.get(&to) {
+            return Ok((&self.sockets[index], to));
+        }
+
+        for socket in self.sockets.iter() {
+            if socket.local_addr()?.is_ipv4() == to.is_ipv4() {
+                return Ok((socket, to));
+            }
+        }
+
+        if let SocketAddr::V4(v4) = to {
+            if let Some(socket) = self.sockets.first() {
+                let mapped = SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port());
+                return Ok((socket, mapped));
+            }
+        }
+
+        let msg = format!("no socket can reach {}", to);
+        Err(Error::new(ErrorKind::AddrNotAvailable, msg))
+    }
+
+    /// Remembers that `from` was heard from on the socket at `index`.
+    fn learn_route(&mut self, from: SocketAddr, index: usize) {
+        if self.routes.insert(from, index).is_none() {
+            self.route_order.push_back(from);
+            if self.route_order.len() > MAX_ROUTES {
+                let oldest = self.route_order.pop_front().unwrap();
+                self.routes.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.sockets[0].local_addr()
+    }
+
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()> {
+        let (socket, to) = self.route(to)?;
+        socket.send_to(datagram, to).map(|_| ())
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        for i in 0..self.sockets.len() {
+            let index = (self.next_recv + i) % self.sockets.len();
+            if let Some((len, from)) = self.sockets[index].try_recv_from(buf)? {
+                self.learn_route(from, index);
+                self.next_recv = (index + 1) % self.sockets.len();
+                return Ok(Some((len, from)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// How long [TcpTransport] waits for a connection to a peer to open.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The length that precedes each datagram sent over TCP.
+const FRAME_HEADER_LEN: usize = 2;
+
+/// The longest a frame can be, header and all.
+const MAX_FRAME_LEN: usize = FRAME_HEADER_LEN + u16::MAX as usize;
+
+/// How many bytes may wait to be sent to a peer before its connection is
+/// dropped, so that a peer that stops reading can't run this node out of
+/// memory.
+const MAX_UNSENT: usize = 16 * MAX_FRAME_LEN;
+
+/// The most connections from peers a [TcpTransport] keeps open. Beyond this,
+/// the one idle longest is closed to make room, so that peers can't run this
+/// node out of sockets.
+const MAX_ACCEPTED: usize = 64;
+
+/// Carries datagrams over TCP, for networks that filter UDP.
+///
+/// Each datagram is sent whole, preceded by its length as a little-endian
+/// `u16`. A connection to a peer is opened in the background the first time
+/// it's sent to, and what's sent to it waits until the connection is open,
+/// for up to [CONNECT_TIMEOUT]. Peers that connect to this transport are
+/// replied to over the connection they opened, so they appear at the
+/// address it came from rather than the one they listen on.
+pub struct TcpTransport {
+    listeners: Vec<TcpListener>,
+    connections: HashMap<SocketAddr, Connection>,
+    /// Addresses that the open connections from peers came from.
+    accepted: HashSet<SocketAddr>,
+    /// Addresses whose connections from peers have closed, up to
+    /// [MAX_ACCEPTED] of the latest. Nothing listens on them, so sending to
+    /// them fails instead of connecting.
+    closed: VecDeque<SocketAddr>,
+    /// Where to start polling connections next time, so that a busy one
+    /// can't starve the others.
+    next_recv: usize,
+}
+
+impl TcpTransport {
+    /// Listens on each of `addrs` without blocking.
+    pub fn bind(addrs: &[SocketAddr]) -> Result<Self> {
+        if addrs.is_empty() {
+            let msg = "no addresses to bind to";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+
+        let listeners = addrs
+            .iter()
+            .map(|addr| {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            listeners,
+            ..Self::outgoing()
+        })
+    }
+
+    /// Opens connections to peers but doesn't accept any.
+    pub fn outgoing() -> Self {
+        Self {
+            listeners: Vec::new(),
+            connections: HashMap::new(),
+            accepted: HashSet::new(),
+            closed: VecDeque::new(),
+            next_recv: 0,
+        }
+    }
+
+    /// The addresses of every listener, in the order they were bound.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// Takes every connection waiting on the listeners.
+    fn accept(&mut self) -> Result<()> {
+        for index in 0..self.listeners.len() {
+            loop {
+                match self.listeners[index].accept() {
+                    Ok((stream, addr)) => {
+                        let connection = Connection::accepted(stream)?;
+                        if self.accepted.len() >= MAX_ACCEPTED {
+                            let idlest = self
+                                .accepted
+                                .iter()
+                                .min_by_key(|addr| self.connections[addr].active);
+                            if let Some(&idlest) = idlest {
+                                self.close(idlest);
+                            }
+                        }
+
+                        self.connections.insert(addr, connection);
+                        self.accepted.insert(addr);
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the connection to `to`, if there is one.
+    ///
+    /// A broken connection this transport opened is reopened the next time
+    /// it's sent to, as if a datagram had been lost. One that a peer opened
+    /// is remembered as closed instead.
+    fn close(&mut self, to: SocketAddr) {
+        self.connections.remove(&to);
+        if self.accepted.remove(&to) {
+            self.closed.push_back(to);
+            if self.closed.len() > MAX_ACCEPTED {
+                self.closed.pop_front();
+            }
+        }
+    }
+
+    /// Whether datagrams to `to` go over a connection, rather than needing
+    /// one to be opened first.
+    fn is_connected(&self, to: SocketAddr) -> bool {
+        self.connections.contains_key(&to) || self.closed.contains(&to)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        match self.listeners.first() {
+            Some(listener) => listener.local_addr(),
+            None => Err(Error::new(ErrorKind::AddrNotAvailable, "not listening")),
+        }
+    }
+
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()> {
+        let len = u16::try_from(datagram.len()).map_err(|_| {
+            let msg = format!("{} byte datagram does not fit in a frame", datagram.len());
+            Error::new(ErrorKind::InvalidInput, msg)
+        })?;
+
+        let connection = match self.connections.entry(to) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(_) if self.closed.contains(&to) => {
+                let msg = format!("{} closed its connection", to);
+                return Err(Error::new(ErrorKind::NotConnected, msg));
+            }
+            Entry::Vacant(entry) => entry.insert(Connection::connect(to)),
+        };
+
+        connection.active = Instant::now();
+        connection.unsent.extend_from_slice(&len.to_le_bytes());
+        connection.unsent.extend_from_slice(datagram);
+        let flushed = connection.flush().and_then(|_| {
+            if connection.unsent.len() > MAX_UNSENT {
+                let msg = format!("{} is not reading what is sent to it", to);
+                return Err(Error::new(ErrorKind::TimedOut, msg));
+            }
+
+            Ok(())
+        });
+
+        if flushed.is_err() {
+            self.close(to);
+        }
+
+        flushed
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        self.accept()?;
+
+        let addrs: Vec<_> = self.connections.keys().copied().collect();
+        let mut received = None;
+        let mut broken = Vec::new();
+        for i in 0..addrs.len() {
+            let index = (self.next_recv + i) % addrs.len();
+            let connection = self.connections.get_mut(&addrs[index]).unwrap();
+            match connection.poll(buf) {
+                Ok(Some(len)) => {
+                    connection.active = Instant::now();
+                    received = Some((len, addrs[index]));
+                    self.next_recv = index + 1;
+                    break;
+                }
+                Ok(None) => {}
+                Err(_) => broken.push(addrs[index]),
+            }
+        }
+
+        for addr in broken {
+            self.close(addr);
+        }
+
+        Ok(received)
+    }
+}
+
+/// A stream to a peer and the parts of frames that haven't made it through
+/// it yet.
+struct Connection {
+    /// The stream, once it's open.
+    stream: Option<TcpStream>,
+    /// Where the stream comes from while it's being opened on another
+    /// thread.
+    connecting: Option<Receiver<Result<TcpStream>>>,
+    /// Bytes read that don't yet make a whole frame.
+    received: Vec<u8>,
+    /// Bytes waiting for the stream to open or for room in the socket's send
+    /// buffer.
+    unsent: Vec<u8>,
+    /// When a frame was last sent or received.
+    active: Instant,
+}
+
+impl Connection {
+    /// Starts opening a connection to `to` in the background, so that
+    /// sending never blocks.
+    fn connect(to: SocketAddr) -> Self {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        thread::spawn(move || {
+            let _ = sender.send(TcpStream::connect_timeout(&to, CONNECT_TIMEOUT));
+        });
+
+        Self {
+            stream: None,
+            connecting: Some(receiver),
+            received: Vec::new(),
+            unsent: Vec::new(),
+            active: Instant::now(),
+        }
+    }
+
+    fn accepted(stream: TcpStream) -> Result<Self> {
+        let mut connection = Self {
+            stream: None,
+            connecting: None,
+            received: Vec::new(),
+            unsent: Vec::new(),
+            active: Instant::now(),
+        };
+
+        connection.open(stream)?;
+        Ok(connection)
+    }
+
+    fn open(&mut self, stream: TcpStream) -> Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Takes the stream once it's been opened, failing if it couldn't be.
+    fn establish(&mut self) -> Result<()> {
+        let connecting = match self.connecting {
+            Some(ref connecting) => connecting,
+            None => return Ok(()),
+        };
+
+        let stream = match connecting.try_recv() {
+            Ok(stream) => stream?,
+            Err(TryRecvError::Empty) => return Ok(()),
+            Err(TryRecvError::Disconnected) => return Err(ErrorKind::NotConnected.into()),
+        };
+
+        self.connecting = None;
+        self.open(stream)
+    }
+
+    /// Writes as much of [Self::unsent] as the socket will take.
+    fn flush(&mut self) -> Result<()> {
+        self.establish()?;
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => return Ok(()),
+        };
+
+        while !self.unsent.is_empty() {
+            match stream.write(&self.unsent) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => drop(self.unsent.drain(..written)),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads whatever has arrived, up to one whole frame's worth, returning
+    /// whether the stream is still open.
+    fn fill(&mut self) -> Result<bool> {
+        let stream = match self.stream {
+            Some(ref mut stream) => stream,
+            None => return Ok(true),
+        };
+
+        let mut buf = [0u8; 4096];
+        while self.received.len() < MAX_FRAME_LEN {
+            let room = (MAX_FRAME_LEN - self.received.len()).min(buf.len());
+            match stream.read(&mut buf[..room]) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.received.extend_from_slice(&buf[..len]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Takes the next whole datagram out of [Self::received] into `buf`.
+    fn next_frame(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        if self.received.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let len = u16::from_le_bytes([self.received[0], self.received[1]]) as usize;
+        let end = FRAME_HEADER_LEN + len;
+        if self.received.len() < end {
+            return Ok(None);
+        }
+
+        if len > buf.len() {
+            let msg = format!("{} byte datagram does not fit in the buffer", len);
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        buf[..len].copy_from_slice(&self.received[FRAME_HEADER_LEN..end]);
+        self.received.drain(..end);
+        Ok(Some(len))
+    }
+
+    /// Sends what it can and receives the next whole datagram if there is
+    /// one, failing once the peer has closed the stream and every datagram
+    /// it sent has been received.
+    fn poll(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        self.flush()?;
+
+        if let Some(len) = self.next_frame(buf)? {
+            return Ok(Some(len));
+        }
+
+        let open = self.fill()?;
+        match self.next_frame(buf)? {
+            Some(len) => Ok(Some(len)),
+            None if open => Ok(None),
+            None => Err(ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+/// How long a peer has to reply over UDP before [FallbackTransport] tries
+/// it over TCP instead.
+const UDP_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The most peers a [FallbackTransport] waits on a UDP reply from at once.
+/// Peers sent to beyond this are only tried over UDP.
+const MAX_UNANSWERED: usize = 64;
+
+/// The most datagrams kept for each peer that hasn't replied over UDP, to
+/// be sent again over TCP. Older ones are given up on, as if lost.
+const MAX_RESENT: usize = 8;
+
+/// Listens on UDP and TCP at the same addresses, so that peers on networks
+/// that filter UDP can still reach this node over TCP.
+///
+/// Peers with a TCP connection open are sent to over it. Others are sent to
+/// over UDP, unless this node prefers TCP because its own network filters
+/// UDP and they haven't been heard from over UDP. Peers that don't reply
+/// over UDP within [UDP_REPLY_TIMEOUT] are sent what they were sent again
+/// over TCP, which leaves a connection open to them from then on.
+pub struct FallbackTransport {
+    udp: UdpTransport,
+    tcp: TcpTransport,
+    /// Peers sent to over UDP that haven't been heard from yet.
+    unanswered: HashMap<SocketAddr, Unanswered>,
+    prefer_tcp: bool,
+}
+
+/// What has been sent over UDP to a peer that hasn't replied yet.
+struct Unanswered {
+    since: Instant,
+    datagrams: VecDeque<Vec<u8>>,
+}
+
+impl FallbackTransport {
+    /// Binds a UDP socket and a TCP listener to each of `addrs`, both on the
+    /// same port even if it's picked by the system.
+    ///
+    /// Only UDP has to bind. If TCP can't, this node still reaches peers
+    /// over TCP, but they can't reach it.
+    pub fn bind(addrs: &[SocketAddr], prefer_tcp: bool) -> Result<Self> {
+        let udp = UdpTransport::bind(addrs)?;
+        let tcp = TcpTransport::bind(&udp.local_addrs()?).unwrap_or_else(|err| {
+            eprintln!("failed to listen for TCP, continuing without: {}", err);
+            TcpTransport::outgoing()
+        });
+
+        Ok(Self {
+            udp,
+            tcp,
+            unanswered: HashMap::new(),
+            prefer_tcp,
+        })
+    }
+
+    /// Keeps `datagram` to send again over TCP in case `to` doesn't reply
+    /// over UDP.
+    fn await_reply(&mut self, datagram: &[u8], to: SocketAddr) {
+        if !self.unanswered.contains_key(&to) && self.unanswered.len() >= MAX_UNANSWERED {
+            return;
+        }
+
+        let unanswered = self.unanswered.entry(to).or_insert_with(|| Unanswered {
+            since: Instant::now(),
+            datagrams: VecDeque::new(),
+        });
+
+        unanswered.datagrams.push_back(datagram.to_vec());
+        if unanswered.datagrams.len() > MAX_RESENT {
+            unanswered.datagrams.pop_front();
+        }
+    }
+
+    /// Switches peers that haven't replied over UDP in time to TCP.
+    fn fall_back(&mut self) -> Result<()> {
+        let expired: Vec<_> = self
+            .unanswered
+            .iter()
+            .filter(|(_, unanswered)| unanswered.since.elapsed() >= UDP_REPLY_TIMEOUT)
+            .map(|(&addr, _)| addr)
+            .collect();
+
+        for addr in expired {
+            let unanswered = self.unanswered.remove(&addr).unwrap();
+            for datagram in unanswered.datagrams {
+                self.tcp.send_to(&datagram, addr)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Transport for FallbackTransport {
+    fn local_addr(&self) -> Result<SocketAddr> {
+        self.udp.local_addr()
+    }
+
+    fn send_to(&mut self, datagram: &[u8], to: SocketAddr) -> Result<()> {
+        // peers heard from over UDP are answered over UDP, even by a node
+        // that prefers TCP, since it evidently gets through
+        let heard_over_udp = self.udp.routes.contains_key(&to);
+        if self.tcp.is_connected(to) || (self.prefer_tcp && !heard_over_udp) {
+            return self.tcp.send_to(datagram, to);
+        }
+
+        self.udp.send_to(datagram, to)?;
+        if !heard_over_udp {
+            self.await_reply(datagram, to);
+        }
+
+        Ok(())
+    }
+
+    fn try_recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        self.fall_back()?;
+
+        if let Some((len, from)) = self.udp.try_recv_from(buf)? {
+            self.unanswered.remove(&from);
+            return Ok(Some((len, from)));
+        }
+
+        if let Some((len, from)) = self.tcp.try_recv_from(buf)? {
+            self.unanswered.remove(&from);
+            return Ok(Some((len, from)));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn udp() {
+        let mut transport = UdpTransport::bind(&[
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 0)),
+        ])
+        .unwrap();
+        let local = transport.local_addrs().unwrap();
+
+        let v4 = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let v6 = UdpSocket::bind("[::1]:0").unwrap();
+        for peer in [&v4, &v6] {
+            peer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        }
+
+        let mut buf = [0u8; 16];
+
+        // replies come from whichever socket the peer reached
+        for (peer, local) in [(&v4, local[0]), (&v6, local[1])] {
+            peer.send_to(b"ping", local).unwrap();
+            let from = loop {
+                if let Some((_, from)) = transport.try_recv_from(&mut buf).unwrap() {
+                    break from;
+                }
+            };
+
+            assert_eq!(from, peer.local_addr().unwrap());
+            transport.send_to(b"pong", from).unwrap();
+            let (len, from) = peer.recv_from(&mut buf).unwrap();
+            assert_eq!((&buf[..len], from), (&b"pong"[..], local));
+        }
+
+        // a dual-stack socket reaches IPv4 peers at their mapped address
+        let mut dual = UdpTransport::bind(&[SocketAddr::from(([0u16; 8], 0))]).unwrap();
+        let dual_port = dual.local_addr().unwrap().port();
+        dual.send_to(b"ping", v4.local_addr().unwrap()).unwrap();
+        let (len, from) = v4.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(from, SocketAddr::from(([127, 0, 0, 1], dual_port)));
+
+        let mut v4_only = UdpTransport::bind(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let err = v4_only
+            .send_to(b"ping", v6.local_addr().unwrap())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AddrNotAvailable);
+    }
+
+    #[test]
+    fn udp_routes() {
+        let mut transport = UdpTransport::bind(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let peer = |port| SocketAddr::from(([192, 0, 2, 1], port));
+        for port in 0..2 * MAX_ROUTES as u16 {
+            transport.learn_route(peer(port), 0);
+        }
+
+        // hearing from a peer again doesn't count it twice
+        transport.learn_route(peer(2 * MAX_ROUTES as u16 - 1), 0);
+
+        assert_eq!(transport.routes.len(), MAX_ROUTES);
+        assert_eq!(transport.route_order.len(), MAX_ROUTES);
+        assert!(!transport.routes.contains_key(&peer(0)));
+        assert!(transport
+            .routes
+            .contains_key(&peer(2 * MAX_ROUTES as u16 - 1)));
+    }
+
+    #[test]
+    fn tcp_port_taken() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+
+        // UDP is enough to run on
+        let transport = FallbackTransport::bind(&[addr], false).unwrap();
+        assert_eq!(transport.local_addr().unwrap(), addr);
+        assert!(transport.tcp.local_addr().is_err());
+    }
+
+    /// Receives from `transport` until a datagram arrives, polling `sender`
+    /// meanwhile so that it sends what it has queued.
+    fn recv_blocking(
+        transport: &mut impl Transport,
+        sender: &mut impl Transport,
+        buf: &mut [u8],
+    ) -> (usize, SocketAddr) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            assert!(sender.try_recv_from(&mut [0; 16]).unwrap().is_none());
+            if let Some(received) = transport.try_recv_from(buf).unwrap() {
+                return received;
+            }
+        }
+
+        panic!("timed out waiting for a datagram");
+    }
+
+    #[test]
+    fn tcp() {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut alice = TcpTransport::bind(&[localhost]).unwrap();
+        let mut bob = TcpTransport::bind(&[localhost]).unwrap();
+        let bob_addr = bob.local_addr().unwrap();
+        let mut buf = [0u8; 16];
+
+        alice.send_to(b"ping", bob_addr).unwrap();
+        alice.send_to(b"", bob_addr).unwrap();
+        let (len, alice_addr) = recv_blocking(&mut bob, &mut alice, &mut buf);
+        assert_eq!(&buf[..len], b"ping");
+        let (len, _) = recv_blocking(&mut bob, &mut alice, &mut buf);
+        assert_eq!(len, 0);
+
+        // replies go back over the connection alice opened
+        bob.send_to(b"pong", alice_addr).unwrap();
+        let (len, from) = recv_blocking(&mut alice, &mut bob, &mut buf);
+        assert_eq!((&buf[..len], from), (&b"pong"[..], bob_addr));
+
+        // frames are put back together however the stream splits them
+        let mut stream = std::net::TcpStream::connect(bob_addr).unwrap();
+        stream.write_all(&[5, 0, b'h', b'e']).unwrap();
+        stream.flush().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(bob.try_recv_from(&mut buf).unwrap().is_none());
+        stream.write_all(b"llo").unwrap();
+        let (len, _) = recv_blocking(&mut bob, &mut alice, &mut buf);
+        assert_eq!(&buf[..len], b"hello");
+
+        // a peer that never reads is dropped rather than buffered for
+        let sink = std::net::TcpListener::bind(localhost).unwrap();
+        let sink_addr = sink.local_addr().unwrap();
+        let datagram = [0u8; 1024];
+        let sent = (0..100_000).take_while(|_| alice.send_to(&datagram, sink_addr).is_ok());
+        assert!(sent.count() < 100_000);
+
+        // alice can't be dialled back at the address she connected from,
+        // once bob notices that her connection has closed
+        drop(alice);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            assert!(Instant::now() < deadline);
+            bob.try_recv_from(&mut buf).unwrap();
+            match bob.send_to(b"ping", alice_addr) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotConnected => break,
+                _ => std::thread::sleep(Duration::from_millis(1)),
+            }
+        }
+
+        assert!(!bob.accepted.contains(&alice_addr));
+    }
+
+    #[test]
+    fn tcp_accept_limit() {
+        let mut bob = TcpTransport::bind(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let bob_addr = bob.local_addr().unwrap();
+        let mut buf = [0u8; 16];
+
+        let mut streams = Vec::new();
+        for _ in 0..MAX_ACCEPTED + 1 {
+            let stream = TcpStream::connect(bob_addr).unwrap();
+            let addr = stream.local_addr().unwrap();
+            streams.push(stream);
+
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !bob.accepted.contains(&addr) {
+                assert!(Instant::now() < deadline);
+                bob.try_recv_from(&mut buf).unwrap();
+            }
+
+            // keeps when each was last active apart
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the connection idle longest makes room for the newest
+        let oldest = streams[0].local_addr().unwrap();
+        let newest = streams[MAX_ACCEPTED].local_addr().unwrap();
+        assert_eq!(bob.accepted.len(), MAX_ACCEPTED);
+        assert_eq!(bob.closed, [oldest]);
+        assert!(bob.accepted.contains(&newest));
+    }
+
+    #[test]
+    fn tcp_fairness() {
+        let mut bob = TcpTransport::bind(&[SocketAddr::from(([127, 0, 0, 1], 0))]).unwrap();
+        let bob_addr = bob.local_addr().unwrap();
+        let mut buf = [0u8; 16];
+
+        let mut streams = [
+            TcpStream::connect(bob_addr).unwrap(),
+            TcpStream::connect(bob_addr).unwrap(),
+        ];
+
+        for stream in streams.iter_mut() {
+            for _ in 0..8 {
+                stream.write_all(&[1, 0, b'x']).unwrap();
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let first = loop {
+            assert!(Instant::now() < deadline);
+            if let Some((_, from)) = bob.try_recv_from(&mut buf).unwrap() {
+                break from;
+            }
+        };
+
+        // a connection with more to read doesn't keep the other waiting
+        std::thread::sleep(Duration::from_millis(50));
+        let (_, second) = bob.try_recv_from(&mut buf).unwrap().unwrap();
+        assert_ne!(first, second);
+    }
+}